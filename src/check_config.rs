@@ -0,0 +1,347 @@
+//! This file contains the checks run by the check subcommand.
+//! Every check only reports problems; the config itself is left untouched.
+use std::collections::BTreeMap;
+
+use crate::register_signals::parse_signal;
+use crate::structs::{
+    action::Action,
+    config::{Config, Field, CONFIG_VERSION},
+    player_rule::PlayerRule,
+    problem::Problem,
+    template::Template,
+};
+use crate::update_player_fields::PLAYER_FIELDS;
+use crate::update_time::TIME_FIELDS;
+
+/// Names of the metadata fields defined by the mpris specification (and a few common extensions).
+const METADATA_FIELDS: [&str; 23] = [
+    "mpris:trackid",
+    "mpris:length",
+    "mpris:artUrl",
+    "xesam:album",
+    "xesam:albumArtist",
+    "xesam:artist",
+    "xesam:asText",
+    "xesam:audioBPM",
+    "xesam:autoRating",
+    "xesam:comment",
+    "xesam:composer",
+    "xesam:contentCreated",
+    "xesam:discNumber",
+    "xesam:firstUsed",
+    "xesam:genre",
+    "xesam:lastUsed",
+    "xesam:lyricist",
+    "xesam:title",
+    "xesam:trackNumber",
+    "xesam:url",
+    "xesam:useCount",
+    "xesam:userRating",
+    "bs:isFavorite",
+];
+
+/// Namespaces whose field names are all known; names in other namespaces are player specific, and not checked.
+const CHECKED_NAMESPACES: [&str; 4] = ["xesam:", "mpris:", "time:", "player:"];
+
+/// This function returns an iterator over all field names the program knows about.
+fn known_fields() -> impl Iterator<Item = &'static str> {
+    METADATA_FIELDS
+        .into_iter()
+        .chain(TIME_FIELDS)
+        .chain(PLAYER_FIELDS)
+}
+
+/// This function computes the Levenshtein distance between two strings, in characters.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (row[j + 1] + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(ca != *cb));
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+/// This function finds the known field name closest to the given name, if any is close enough to be a typo.
+fn suggest(name: &str) -> Option<&'static str> {
+    known_fields()
+        .map(|known| (distance(&name.to_lowercase(), &known.to_lowercase()), known))
+        .filter(|(d, _)| *d <= 2)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, known)| known)
+}
+
+/// This function checks whether a field name refers to a field which exists.
+///
+/// Input:
+/// location: path of the key the name was found at.
+/// name: the field name.
+/// out: list of problems to add to.
+fn check_field_name(location: &str, name: &str, out: &mut Vec<Problem>) {
+    if known_fields().any(|known| known == name) {
+        return;
+    }
+    if !name.contains(':') {
+        let message = match known_fields().any(|known| known == format!("xesam:{name}")) {
+            true => format!("\"{name}\" has no namespace; did you mean \"xesam:{name}\"?"),
+            false => format!("\"{name}\" has no namespace, so no player will ever provide it"),
+        };
+        out.push(Problem::error(location, message));
+        return;
+    }
+    if !CHECKED_NAMESPACES.iter().any(|ns| name.starts_with(ns)) {
+        return;
+    }
+    let message = match suggest(name) {
+        Some(known) => format!("unknown field \"{name}\"; did you mean \"{known}\"?"),
+        None => format!("unknown field \"{name}\""),
+    };
+    out.push(Problem::error(location, message));
+}
+
+/// This function checks a list of Fields.
+///
+/// Input:
+/// location: path of the list, ie `metadata_fields`.
+/// fields: the Fields to check.
+/// out: list of problems to add to.
+fn check_fields(location: &str, fields: &[Field], out: &mut Vec<Problem>) {
+    for (i, field) in fields.iter().enumerate() {
+        let location = format!("{location}[{i}]");
+        check_field_name(&format!("{location}.field"), &field.field, out);
+        if field.num_chars == 0 {
+            out.push(Problem::error(
+                format!("{location}.num_chars"),
+                "must be at least 1, otherwise the field is always empty",
+            ));
+        }
+        if !field.format.contains("{}") {
+            out.push(Problem::error(
+                format!("{location}.format"),
+                format!("\"{}\" does not contain \"{{}}\", so the value of the field is never shown", field.format),
+            ));
+        }
+        if let Some(Err(e)) = field.click.as_deref().map(str::parse::<Action>) {
+            out.push(Problem::error(format!("{location}.click"), e));
+        }
+    }
+}
+
+/// This function checks a format template.
+///
+/// Input:
+/// location: path of the format key.
+/// format: the template as written in the config file.
+/// out: list of problems to add to.
+fn check_format(location: &str, format: &str, out: &mut Vec<Problem>) {
+    match Template::parse(format) {
+        Ok(template) => {
+            for name in template.fields() {
+                check_field_name(location, name, out);
+            }
+        }
+        Err(e) => out.push(Problem::error(location, e.to_string())),
+    }
+}
+
+/// This function checks the player_priorities and player_prefixes tables.
+///
+/// Input:
+/// cfg: the Config to check.
+/// out: list of problems to add to.
+fn check_players(cfg: &Config, out: &mut Vec<Problem>) {
+    let mut by_priority: BTreeMap<u8, Vec<&str>> = BTreeMap::new();
+    for (key, priority) in &cfg.player_priorities {
+        by_priority.entry(*priority).or_default().push(key);
+    }
+    for (priority, keys) in by_priority.into_iter().filter(|(_, keys)| keys.len() > 1) {
+        out.push(Problem::warning(
+            "player_priorities",
+            format!(
+                "{} share priority {priority}; which one is shown is decided by tie_breaker",
+                keys.iter().map(|k| format!("\"{k}\"")).collect::<Vec<_>>().join(", ")
+            ),
+        ));
+    }
+
+    let keys = cfg
+        .player_priorities
+        .keys()
+        .map(|k| ("player_priorities", k))
+        .chain(cfg.player_prefixes.keys().map(|k| ("player_prefixes", k)));
+    for (table, key) in keys {
+        if key.starts_with("regex:") || key.starts_with("bus:") {
+            if let Err(e) = PlayerRule::parse(key) {
+                out.push(Problem::error(table, e));
+            }
+        }
+    }
+
+    if !cfg.player_prefixes.contains_key("default") {
+        out.push(Problem::warning(
+            "player_prefixes",
+            "no \"default\" entry; \">\" is used for players without a prefix",
+        ));
+    }
+}
+
+/// This function checks the signal_actions and click_actions tables.
+///
+/// Input:
+/// cfg: the Config to check.
+/// out: list of problems to add to.
+fn check_actions(cfg: &Config, out: &mut Vec<Problem>) {
+    let mut signals: Vec<_> = cfg.signal_actions.iter().collect();
+    signals.sort();
+    for (name, action) in signals {
+        let location = format!("signal_actions.{name}");
+        if let Err(e) = parse_signal(name) {
+            out.push(Problem::error(&location, e));
+        }
+        if let Err(e) = action.parse::<Action>() {
+            out.push(Problem::error(&location, e));
+        }
+    }
+
+    let mut clicks: Vec<_> = cfg.click_actions.iter().collect();
+    clicks.sort();
+    for (button, action) in clicks {
+        let location = format!("click_actions.{button}");
+        if let Err(e) = button.parse::<u8>() {
+            out.push(Problem::error(&location, format!("invalid mouse button: {e}")));
+        }
+        if let Err(e) = action.parse::<Action>() {
+            out.push(Problem::error(&location, e));
+        }
+    }
+}
+
+/// This function checks a config for mistakes which would otherwise only show up as odd output.
+///
+/// Input:
+/// cfg: the Config to check, as loaded from the config file.
+///
+/// Returns:
+/// every problem found, in the order of the checks.
+pub fn check_config(cfg: &Config) -> Vec<Problem> {
+    let mut out: Vec<_> = cfg
+        .unknown_keys
+        .iter()
+        .map(|key| Problem::error(key, "unknown key; it is ignored"))
+        .collect();
+    if cfg.config_version < CONFIG_VERSION {
+        out.push(Problem::warning(
+            "config_version",
+            format!(
+                "the file is for schema version {}; run the migrate subcommand to upgrade it to {CONFIG_VERSION}",
+                cfg.config_version
+            ),
+        ));
+    }
+    check_fields("metadata_fields", &cfg.metadata_fields, &mut out);
+    check_fields("alt_metadata_fields", &cfg.alt_metadata_fields, &mut out);
+    if let Some(format) = &cfg.format {
+        check_format("format", format, &mut out);
+    }
+    check_players(cfg, &mut out);
+    check_actions(cfg, &mut out);
+
+    let mut overrides: Vec<_> = cfg.player_overrides.iter().collect();
+    overrides.sort_by_key(|(identity, _)| *identity);
+    for (identity, o) in overrides {
+        let location = format!("player_overrides.{identity}");
+        if let Some(fields) = &o.metadata_fields {
+            check_fields(&format!("{location}.metadata_fields"), fields, &mut out);
+        }
+        if let Some(format) = &o.format {
+            check_format(&format!("{location}.format"), format, &mut out);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_config, distance, suggest};
+    use crate::structs::config::{Config, Field};
+    use crate::structs::problem::Severity;
+
+    fn locations(cfg: &Config) -> Vec<(Severity, String)> {
+        check_config(cfg)
+            .into_iter()
+            .map(|p| (p.severity, p.location))
+            .collect()
+    }
+
+    #[test]
+    fn default_config_has_no_problems() {
+        let problems = check_config(&Config::default());
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn typos_get_a_suggestion() {
+        assert_eq!(distance("xesam:titel", "xesam:title"), 2);
+        assert_eq!(suggest("xesam:titel"), Some("xesam:title"));
+        assert_eq!(suggest("xesam:Artist"), Some("xesam:artist"));
+        assert_eq!(suggest("time:elapsed"), None);
+    }
+
+    #[test]
+    fn problems_report_their_location() {
+        let mut cfg = Config {
+            metadata_fields: vec![
+                Field::constructor("xesam:titel", 0, None),
+                Field::constructor("artist", 10, Some("by".to_owned())),
+                Field::constructor("spotify:custom", 10, None),
+            ],
+            format: Some("{album} {time:positon}".to_owned()),
+            ..Default::default()
+        };
+        cfg.player_priorities.insert("regex:(".to_owned(), 9);
+        cfg.player_priorities.insert("mpv".to_owned(), 9);
+        cfg.player_prefixes.shift_remove("default");
+        cfg.click_actions.insert("left".to_owned(), "jump".to_owned());
+
+        let problems = check_config(&cfg);
+        assert!(problems[0].message.contains("\"xesam:title\""));
+        assert!(problems[2].message.contains("\"xesam:artist\""));
+        assert_eq!(
+            locations(&cfg),
+            vec![
+                (Severity::Error, "metadata_fields[0].field".to_owned()),
+                (Severity::Error, "metadata_fields[0].num_chars".to_owned()),
+                (Severity::Error, "metadata_fields[1].field".to_owned()),
+                (Severity::Error, "metadata_fields[1].format".to_owned()),
+                (Severity::Error, "format".to_owned()),
+                (Severity::Warning, "player_priorities".to_owned()),
+                (Severity::Error, "player_priorities".to_owned()),
+                (Severity::Warning, "player_prefixes".to_owned()),
+                (Severity::Error, "click_actions.left".to_owned()),
+                (Severity::Error, "click_actions.left".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_errors() {
+        let cfg = Config {
+            unknown_keys: vec!["update_dealy".to_owned(), "metadata_fields[0].formatt".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(
+            locations(&cfg),
+            vec![
+                (Severity::Error, "update_dealy".to_owned()),
+                (Severity::Error, "metadata_fields[0].formatt".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! This file deals with the per-instance Unix socket through which a running instance can be controlled.
+//! Requests and replies are single lines of JSON: an Action goes in, a Reply comes out.
+use std::fs::{self, DirBuilder};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, error, warn};
+
+use crate::structs::{
+    action::{Action, Reply},
+    event::Event,
+};
+
+/// How long to wait for the main loop to handle a command before giving up.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a client to send its' command, or to take its' reply, before dropping the connection.
+const IO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Longest command accepted, in bytes; no Action comes close.
+const MAX_REQUEST: u64 = 64 * 1024;
+
+/// This function returns the id of the user running the program.
+fn uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    unsafe { libc::getuid() }
+}
+
+/// This function returns the directory containing the sockets of all running instances.
+/// This is $XDG_RUNTIME_DIR/polybar-now-playing, or a directory per user in /tmp if that variable is not set.
+fn socket_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("polybar-now-playing"),
+        None => std::env::temp_dir().join(format!("polybar-now-playing-{}", uid())),
+    }
+}
+
+/// This function checks that the socket directory is a directory owned by the current user.
+/// Otherwise another user could put sockets in it (or read them), as /tmp is shared.
+fn check_socket_dir(dir: &Path) -> Result<(), String> {
+    let meta = fs::symlink_metadata(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    if !meta.is_dir() {
+        return Err(format!("{}: not a directory", dir.display()));
+    }
+    if meta.uid() != uid() {
+        return Err(format!("{}: owned by another user", dir.display()));
+    }
+    Ok(())
+}
+
+/// This function creates the socket directory (if it does not exist yet), accessible to the current user only.
+fn create_socket_dir(dir: &Path) -> Result<(), String> {
+    match DirBuilder::new().recursive(true).mode(0o700).create(dir) {
+        Ok(()) => check_socket_dir(dir),
+        Err(e) => Err(format!("{}: {e}", dir.display())),
+    }
+}
+
+/// This function returns the socket path of the instance with the given name.
+pub fn socket_path(instance: &str) -> PathBuf {
+    socket_dir().join(format!("{instance}.sock"))
+}
+
+/// This function checks whether an instance is still listening on the given socket.
+/// Sockets left behind by instances that are no longer running (refusing connections) are removed;
+/// sockets which cannot be connected to for other reasons (ie permissions) are left alone.
+fn is_live(path: &Path) -> bool {
+    match UnixStream::connect(path) {
+        Ok(_) => true,
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            debug!("is_live: removing stale socket {}", path.display());
+            let _ = fs::remove_file(path);
+            false
+        }
+        Err(e) => {
+            debug!("is_live: {}: {e}", path.display());
+            false
+        }
+    }
+}
+
+/// This function finds the socket to connect to.
+/// If no instance name is given, there has to be exactly one running instance.
+fn find_socket(instance: Option<&str>) -> Result<PathBuf, String> {
+    let dir = socket_dir();
+    if dir.exists() {
+        check_socket_dir(&dir)?;
+    }
+    if let Some(instance) = instance {
+        return Ok(socket_path(instance));
+    }
+
+    let sockets: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("no running instances found ({e})"))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sock") && is_live(path))
+        .collect();
+    match sockets.len() {
+        0 => Err("no running instances found".to_owned()),
+        1 => Ok(sockets[0].to_owned()),
+        _ => Err(format!(
+            "multiple running instances found, specify one with --instance: {}",
+            sockets
+                .iter()
+                .filter_map(|p| p.file_stem())
+                .map(|s| s.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// This function handles a single connection to the control socket.
+/// It reads one Action, passes it to the main loop and writes back the resulting Reply.
+/// Clients which do not send a full line (or do not read the reply) in time are dropped.
+///
+/// Input:
+/// stream: the accepted connection.
+/// tx: Sender through which the Action is passed to the main loop.
+fn handle_connection(stream: UnixStream, tx: &Sender<Event>) {
+    let timeouts = stream
+        .set_read_timeout(Some(IO_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)));
+    if let Err(e) = timeouts {
+        debug!("handle_connection: {e}");
+        return;
+    }
+    let mut line = String::new();
+    match BufReader::new((&stream).take(MAX_REQUEST)).read_line(&mut line) {
+        Ok(0) => return,
+        Ok(_) => (),
+        Err(e) => {
+            debug!("handle_connection: {e}");
+            return;
+        }
+    }
+
+    let reply = match serde_json::from_str::<Action>(&line) {
+        Ok(action) => {
+            debug!("handle_connection: received {action:?}");
+            let (reply_tx, reply_rx) = mpsc::channel();
+            match tx.send(Event::Command(action, reply_tx)) {
+                Ok(_) => reply_rx
+                    .recv_timeout(REPLY_TIMEOUT)
+                    .unwrap_or_else(|_| Reply::err("timed out waiting for the main loop")),
+                Err(_) => Reply::err("main loop is not running"),
+            }
+        }
+        Err(e) => Reply::err(format!("invalid command: {e}")),
+    };
+
+    let mut stream = &stream;
+    let written = serde_json::to_writer(stream, &reply)
+        .map_err(|e| e.to_string())
+        .and_then(|_| stream.write_all(b"\n").map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        debug!("handle_connection: {e}");
+    }
+}
+
+/// This function binds the control socket for the given instance and spawns a thread serving it.
+/// A stale socket left behind by a previous instance is replaced; a live one is left alone.
+///
+/// Input:
+/// instance: name of this instance.
+/// tx: Sender through which received Actions are passed to the main loop.
+///
+/// Returns:
+/// whether the socket is being listened on.
+pub fn listen_socket(instance: &str, tx: Sender<Event>) -> bool {
+    if let Err(e) = create_socket_dir(&socket_dir()) {
+        error!("listen_socket: {e}");
+        return false;
+    }
+    let path = socket_path(instance);
+    if UnixStream::connect(&path).is_ok() {
+        error!("listen_socket: an instance named {instance} is already running!");
+        return false;
+    }
+    let _ = fs::remove_file(&path);
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("listen_socket: {e}");
+            return false;
+        }
+    };
+    debug!("listen_socket: listening on {}", path.display());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    // each connection gets its' own thread, so a slow client never holds up the others.
+                    let tx = tx.clone();
+                    thread::spawn(move || handle_connection(stream, &tx));
+                }
+                Err(e) => warn!("listen_socket: {e}"),
+            }
+        }
+    });
+    true
+}
+
+/// This function quotes a string for use as a single shell word.
+fn shell_quote(str: &str) -> String {
+    format!("'{}'", str.replace('\'', "'\\''"))
+}
+
+/// This function builds the shell command which sends an action to the given instance, minus the action itself.
+/// It is used for commands that are run by the bar, such as polybar's action tags.
+///
+/// Input:
+/// instance: name of the instance the command should control.
+///
+/// Returns:
+/// the command, or None if the path of the running executable cannot be determined.
+pub fn ctl_command(instance: &str) -> Option<String> {
+    match std::env::current_exe() {
+        Ok(exe) => Some(format!(
+            "{} --instance {} ctl",
+            shell_quote(&exe.to_string_lossy()),
+            shell_quote(instance)
+        )),
+        Err(e) => {
+            error!("ctl_command: {e}");
+            None
+        }
+    }
+}
+
+/// This function sends an Action to a running instance and waits for its' Reply.
+///
+/// Input:
+/// instance: name of the instance to control; optional if only one instance is running.
+/// action: the Action to send.
+///
+/// Returns:
+/// the Reply of the instance, or a description of why it could not be reached.
+pub fn send_action(instance: Option<&str>, action: &Action) -> Result<Reply, String> {
+    let path = find_socket(instance)?;
+    let mut stream =
+        UnixStream::connect(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut request = serde_json::to_string(action).map_err(|e| e.to_string())?;
+    request.push('\n');
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    serde_json::from_str(&line).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    use super::{create_socket_dir, handle_connection, is_live, IO_TIMEOUT};
+
+    /// This function returns a path for one test, removing anything left behind by a previous run.
+    fn test_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pnp-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn silent_clients_are_dropped() {
+        let (tx, _rx) = mpsc::channel();
+        let (server, _client) = UnixStream::pair().unwrap();
+        let start = Instant::now();
+        handle_connection(server, &tx);
+        assert!(start.elapsed() < IO_TIMEOUT * 2);
+    }
+
+    #[test]
+    fn invalid_commands_get_an_error() {
+        let (tx, _rx) = mpsc::channel();
+        let (server, mut client) = UnixStream::pair().unwrap();
+        client.write_all(b"{\"command\":\"jump\"}\n").unwrap();
+        handle_connection(server, &tx);
+        let mut line = String::new();
+        BufReader::new(&client).read_line(&mut line).unwrap();
+        assert!(line.starts_with("{\"ok\":false"), "{line}");
+        assert!(line.ends_with('\n'));
+    }
+
+    #[test]
+    fn socket_dir_is_private() {
+        let dir = test_path("socket-dir");
+        create_socket_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+        // an existing directory is reused.
+        create_socket_dir(&dir).unwrap();
+
+        let link = test_path("socket-link");
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        assert!(create_socket_dir(&link).unwrap_err().ends_with("not a directory"));
+    }
+
+    #[test]
+    fn only_refused_sockets_are_removed() {
+        let dir = test_path("stale");
+        create_socket_dir(&dir).unwrap();
+        let path = dir.join("stale.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        assert!(is_live(&path));
+        drop(listener);
+        assert!(!is_live(&path));
+        assert!(!path.exists());
+    }
+}
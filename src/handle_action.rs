@@ -0,0 +1,382 @@
+//! This file deals with performing Actions on the active player.
+use std::collections::HashMap;
+
+use log::{debug, error, info, warn};
+use mpris::{DBusError, LoopStatus, Player, PlayerFinder};
+
+use crate::structs::{
+    action::{Action, Reply},
+    config::Config,
+    data::Data,
+    player_rule::PlayerInfo,
+};
+
+/// This function turns the result of one of mpris' checked functions into a Reply, logging it along the way.
+///
+/// Input:
+/// res: Result of the checked function; Ok(false) means the player does not support the operation.
+/// what: description of the operation, for use in messages.
+fn checked(res: Result<bool, DBusError>, what: &str) -> Reply {
+    match res {
+        Ok(true) => {
+            info!("{what} succesful!");
+            Reply::ok(format!("{what} succesful"))
+        }
+        Ok(false) => {
+            warn!("Player does not support {what}!");
+            Reply::err(format!("player does not support {what}"))
+        }
+        Err(e) => {
+            error!("{e}");
+            Reply::err(e.to_string())
+        }
+    }
+}
+
+/// This function finds the player with the given identity or bus name, and marks it as picked in Data.
+/// Identities are compared case-insensitively; bus names may be given with or without the mpris prefix.
+///
+/// Input:
+/// pf: PlayerFinder instance of the program.
+/// data: mutable Data struct of the program.
+/// name: identity or bus name of the player to pick.
+fn pick_player(pf: &PlayerFinder, data: &mut Data, name: &str) -> Reply {
+    let players = match pf.find_all() {
+        Ok(players) => players,
+        Err(e) => return Reply::err(e.to_string()),
+    };
+    match players.iter().find(|p| {
+        p.identity().eq_ignore_ascii_case(name)
+            || p.bus_name() == name
+            || p.bus_name_player_name_part() == name
+    }) {
+        Some(player) => {
+            debug!("pick_player: picked {}", player.bus_name());
+            data.pinned_player = Some(player.bus_name().to_owned());
+            Reply::ok(format!("picked {}", player.identity()))
+        }
+        None => Reply::err(format!("no player named {name} found")),
+    }
+}
+
+/// This function computes the index of the player to cycle to.
+/// After the last player it wraps around to the first, and vice versa.
+///
+/// Input:
+/// len: number of players to cycle through; at least 1.
+/// current: index of the active player among them; None if there is no active player, or it is not among them.
+/// forward: whether to pick the following player rather than the preceding one.
+///
+/// Returns:
+/// the index of the player to pick; the first player if current is None.
+fn cycle_index(len: usize, current: Option<usize>, forward: bool) -> usize {
+    match (current, forward) {
+        (None, _) => 0,
+        (Some(idx), true) => (idx + 1) % len,
+        (Some(idx), false) => (idx + len - 1) % len,
+    }
+}
+
+/// This function picks and pins the player following (or preceding) the active one, ordered by priority (and bus name for equal priorities).
+/// After the last player it wraps around to the first, and vice versa.
+///
+/// Input:
+/// pf: PlayerFinder instance of the program.
+/// cfg: Config struct of the program, containing the player priorities.
+/// data: mutable Data struct of the program.
+/// forward: whether to pick the following player rather than the preceding one.
+fn cycle_player(pf: &PlayerFinder, cfg: &Config, data: &mut Data, forward: bool) -> Reply {
+    let players = match pf.find_all() {
+        Ok(players) => players,
+        Err(e) => return Reply::err(e.to_string()),
+    };
+    let mut players: Vec<_> = players
+        .into_iter()
+        .filter_map(|p| {
+            let info = PlayerInfo::new(&p, cfg.uses_desktop_entry());
+            let priority = cfg.find_player_priorities_idx(&info)?;
+            Some(((priority, info.bus_name), p))
+        })
+        .collect();
+    if players.is_empty() {
+        return Reply::err("no players found");
+    }
+    players.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let players: Vec<_> = players.into_iter().map(|(_, p)| p).collect();
+
+    let current = data
+        .current_player
+        .as_ref()
+        .and_then(|current| players.iter().position(|p| p.bus_name() == current.bus_name()));
+    let player = &players[cycle_index(players.len(), current, forward)];
+    debug!("cycle_player: picked {}", player.bus_name());
+    data.pinned_player = Some(player.bus_name().to_owned());
+    Reply::ok(format!("picked {}", player.identity()))
+}
+
+/// This function pins the active player, so it is kept regardless of priorities.
+///
+/// Input:
+/// data: mutable Data struct of the program.
+fn pin_current(data: &mut Data) -> Reply {
+    match &data.current_player {
+        Some(p) => {
+            debug!("pin_current: pinned {}", p.bus_name());
+            data.pinned_player = Some(p.bus_name().to_owned());
+            Reply::ok(format!("pinned {}", p.identity()))
+        }
+        None => Reply::err("no active player"),
+    }
+}
+
+/// This function releases the pinned player, if any.
+///
+/// Input:
+/// data: mutable Data struct of the program.
+fn unpin(data: &mut Data) -> Reply {
+    match data.pinned_player.take() {
+        Some(name) => Reply::ok(format!("unpinned {name}")),
+        None => Reply::err("no player is pinned"),
+    }
+}
+
+/// This function switches between the regular and the alternative layout.
+///
+/// Input:
+/// cfg: Config struct of the program, containing the alternative layout.
+/// data: mutable Data struct of the program.
+fn toggle_layout(cfg: &Config, data: &mut Data) -> Reply {
+    if cfg.alt_metadata_fields.is_empty() {
+        return Reply::err("no alt_metadata_fields configured");
+    }
+    data.alt_layout = !data.alt_layout;
+    data.field_text.clear();
+    match data.alt_layout {
+        true => Reply::ok("switched to alternative layout"),
+        false => Reply::ok("switched to default layout"),
+    }
+}
+
+/// This function calls the given function on the active player, if there is one.
+///
+/// Input:
+/// data: Data struct of the program, containing the active player.
+/// f: function performing the action on the player.
+fn with_player(data: &Data, f: impl FnOnce(&Player) -> Reply) -> Reply {
+    match &data.current_player {
+        Some(p) => f(p),
+        None => {
+            debug!("with_player: no active player");
+            Reply::err("no active player")
+        }
+    }
+}
+
+/// This function computes the volume after changing it by the given amount, keeping it between 0.0 and 1.0.
+/// Raising the volume also forgets any volume remembered from muting the player.
+///
+/// Input:
+/// muted_volumes: HashMap of bus names of muted players to the volume they had before being muted.
+/// bus_name: bus name of the player.
+/// volume: current volume of the player.
+/// delta: amount to change the volume by.
+///
+/// Returns:
+/// the volume to set.
+fn stepped_volume(muted_volumes: &mut HashMap<String, f64>, bus_name: &str, volume: f64, delta: f64) -> f64 {
+    if delta > 0.0 {
+        muted_volumes.remove(bus_name);
+    }
+    (volume + delta).clamp(0.0, 1.0)
+}
+
+/// This function computes the volume after toggling mute: a player which is not silent is muted and its' volume remembered,
+/// a silent player gets the volume it had before it was muted back.
+///
+/// Input:
+/// muted_volumes: HashMap of bus names of muted players to the volume they had before being muted.
+/// bus_name: bus name of the player.
+/// volume: current volume of the player.
+///
+/// Returns:
+/// the volume to set; None if the player is silent, but was not muted by this program.
+fn toggled_volume(muted_volumes: &mut HashMap<String, f64>, bus_name: &str, volume: f64) -> Option<f64> {
+    match volume > 0.0 {
+        true => {
+            muted_volumes.insert(bus_name.to_owned(), volume);
+            Some(0.0)
+        }
+        false => muted_volumes.remove(bus_name),
+    }
+}
+
+/// This function changes the volume of the active player by the given amount (see stepped_volume).
+///
+/// Input:
+/// data: mutable Data struct of the program.
+/// delta: amount to change the volume by.
+fn change_volume(data: &mut Data, delta: f64) -> Reply {
+    let Some(p) = &data.current_player else {
+        return Reply::err("no active player");
+    };
+    match p.get_volume() {
+        Ok(volume) => {
+            let volume = stepped_volume(&mut data.muted_volumes, p.bus_name(), volume, delta);
+            checked(p.checked_set_volume(volume), "set volume")
+        }
+        Err(e) => Reply::err(e.to_string()),
+    }
+}
+
+/// This function mutes the active player, remembering its' volume so toggling again restores it.
+///
+/// Input:
+/// data: mutable Data struct of the program, containing the remembered volumes.
+fn mute_toggle(data: &mut Data) -> Reply {
+    let Some(p) = &data.current_player else {
+        return Reply::err("no active player");
+    };
+    let volume = match p.get_volume() {
+        Ok(volume) => volume,
+        Err(e) => return Reply::err(e.to_string()),
+    };
+
+    let what = match volume > 0.0 {
+        true => "mute",
+        false => "unmute",
+    };
+    match toggled_volume(&mut data.muted_volumes, p.bus_name(), volume) {
+        Some(volume) => checked(p.checked_set_volume(volume), what),
+        None => Reply::err("player is silent, but was not muted by this program"),
+    }
+}
+
+/// This function turns shuffle of the given player on or off.
+///
+/// Input:
+/// p: the active player.
+fn toggle_shuffle(p: &Player) -> Reply {
+    match p.get_shuffle() {
+        Ok(shuffle) => checked(p.checked_set_shuffle(!shuffle), "toggle shuffle"),
+        Err(e) => Reply::err(e.to_string()),
+    }
+}
+
+/// This function moves the loop status of the given player to the next one, in the order none, playlist, track.
+///
+/// Input:
+/// p: the active player.
+fn cycle_loop(p: &Player) -> Reply {
+    let next = match p.get_loop_status() {
+        Ok(LoopStatus::None) => LoopStatus::Playlist,
+        Ok(LoopStatus::Playlist) => LoopStatus::Track,
+        Ok(LoopStatus::Track) => LoopStatus::None,
+        Err(e) => return Reply::err(e.to_string()),
+    };
+    checked(p.checked_set_loop_status(next), "cycle loop")
+}
+
+/// This function performs the given Action.
+/// All actions except the ones changing the active player or layout act on Data::current_player.
+///
+/// Input:
+/// action: the Action to perform.
+/// pf: PlayerFinder instance of the program.
+/// cfg: Config struct of the program.
+/// data: mutable Data struct of the program.
+///
+/// Returns:
+/// Reply describing whether the action succeeded.
+pub fn handle_action(action: &Action, pf: &PlayerFinder, cfg: &Config, data: &mut Data) -> Reply {
+    match action {
+        Action::PickPlayer { player } => pick_player(pf, data, player),
+        Action::CycleNext => cycle_player(pf, cfg, data, true),
+        Action::CyclePrevious => cycle_player(pf, cfg, data, false),
+        Action::PinCurrent => pin_current(data),
+        Action::Unpin => unpin(data),
+        Action::ToggleLayout => toggle_layout(cfg, data),
+        Action::VolumeUp => change_volume(data, cfg.volume_step),
+        Action::VolumeDown => change_volume(data, -cfg.volume_step),
+        Action::MuteToggle => mute_toggle(data),
+        Action::PlayPause => with_player(data, |p| checked(p.checked_play_pause(), "play/pause")),
+        Action::Next => with_player(data, |p| checked(p.checked_next(), "next")),
+        Action::Previous => with_player(data, |p| checked(p.checked_previous(), "previous")),
+        Action::Stop => with_player(data, |p| checked(p.checked_stop(), "stop")),
+        Action::Raise => with_player(data, |p| checked(p.checked_raise(), "raise")),
+        Action::Seek { offset } => with_player(data, |p| {
+            checked(p.checked_seek((offset * 1e6) as i64), "seek")
+        }),
+        Action::SetVolume { volume } => with_player(data, |p| {
+            checked(p.checked_set_volume(*volume), "set volume")
+        }),
+        Action::ToggleShuffle => with_player(data, toggle_shuffle),
+        Action::CycleLoop => with_player(data, cycle_loop),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{cycle_index, pin_current, stepped_volume, toggled_volume, unpin};
+    use crate::structs::data::Data;
+
+    const BUS: &str = "org.mpris.MediaPlayer2.mpv";
+
+    #[test]
+    fn volume_steps_stay_in_range() {
+        let mut muted = HashMap::new();
+        assert_eq!(stepped_volume(&mut muted, BUS, 0.5, 0.25), 0.75);
+        assert_eq!(stepped_volume(&mut muted, BUS, 0.9, 0.25), 1.0);
+        assert_eq!(stepped_volume(&mut muted, BUS, 0.1, -0.25), 0.0);
+    }
+
+    #[test]
+    fn muting_restores_the_previous_volume() {
+        let mut muted = HashMap::new();
+        assert_eq!(toggled_volume(&mut muted, BUS, 0.6), Some(0.0));
+        assert_eq!(muted[BUS], 0.6);
+        assert_eq!(toggled_volume(&mut muted, BUS, 0.0), Some(0.6));
+        assert!(muted.is_empty());
+
+        // silent, but not muted by this program.
+        assert_eq!(toggled_volume(&mut muted, BUS, 0.0), None);
+
+        // lowering the volume of a muted player keeps the remembered volume, raising it forgets it.
+        toggled_volume(&mut muted, BUS, 0.6);
+        stepped_volume(&mut muted, BUS, 0.0, -0.05);
+        assert_eq!(muted[BUS], 0.6);
+        stepped_volume(&mut muted, BUS, 0.0, 0.05);
+        assert_eq!(toggled_volume(&mut muted, BUS, 0.0), None);
+    }
+
+    #[test]
+    fn cycling_wraps_around() {
+        let cases = [
+            // (number of players, index of the active player, forward, expected)
+            (3, Some(0), true, 1),
+            (3, Some(2), true, 0),
+            (3, Some(0), false, 2),
+            (3, Some(2), false, 1),
+            (1, Some(0), true, 0),
+            (1, Some(0), false, 0),
+            (3, None, true, 0),
+            (3, None, false, 0),
+        ];
+        for (len, current, forward, expected) in cases {
+            assert_eq!(cycle_index(len, current, forward), expected, "{current:?} of {len}, forward: {forward}");
+        }
+    }
+
+    #[test]
+    fn pinning_needs_a_player() {
+        let mut data = Data::default();
+        assert!(!pin_current(&mut data).ok);
+        assert!(!unpin(&mut data).ok);
+
+        data.pinned_player = Some(BUS.to_owned());
+        assert!(unpin(&mut data).ok);
+        assert_eq!(data.pinned_player, None);
+        assert!(!unpin(&mut data).ok);
+    }
+}
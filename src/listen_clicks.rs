@@ -0,0 +1,87 @@
+//! This file deals with the click events i3bar sends on stdin.
+use std::io::{self, BufRead};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use log::{debug, error, trace};
+
+use crate::structs::{action::Action, config::Config, event::Event, output::I3barClick};
+
+/// This function looks up the Action configured for a mouse button in the click_actions table.
+/// It is looked up on every click, so changes to the table apply as soon as the config is reloaded.
+///
+/// Input:
+/// cfg: Config struct of the program, containing the click_actions table.
+/// button: the mouse button clicked.
+///
+/// Returns:
+/// the Action to perform; None if the button has no (valid) action.
+pub fn click_action(cfg: &Config, button: u8) -> Option<Action> {
+    let action = cfg.click_actions.get(&button.to_string())?;
+    match action.parse::<Action>() {
+        Ok(action) => Some(action),
+        Err(e) => {
+            error!("click_actions: {button}: {e}");
+            None
+        }
+    }
+}
+
+/// This function spawns a thread reading click events from stdin.
+/// i3bar sends an infinite JSON array with one event per line; the leading '[' and separating ',' are skipped.
+/// Every click is passed to the main loop, which looks up the action of its' button.
+///
+/// Input:
+/// tx: Sender through which clicks are passed to the main loop.
+pub fn listen_clicks(tx: Sender<Event>) {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("listen_clicks: {e}");
+                    return;
+                }
+            };
+            let json = line.trim().trim_start_matches(['[', ',']);
+            if json.is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str::<I3barClick>(json) {
+                Ok(click) => {
+                    trace!(
+                        "listen_clicks: button {} on {} ({})",
+                        click.button,
+                        click.name,
+                        click.instance
+                    );
+                    if tx.send(Event::Click(click.button)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => debug!("listen_clicks: ignoring {json}: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::click_action;
+    use crate::structs::{action::Action, config::Config};
+
+    #[test]
+    fn buttons_map_to_their_action() {
+        let mut cfg = Config::default();
+        cfg.click_actions.clear();
+        cfg.click_actions.insert("1".to_owned(), "play-pause".to_owned());
+        cfg.click_actions.insert("3".to_owned(), "jump".to_owned());
+        assert!(matches!(click_action(&cfg, 1), Some(Action::PlayPause)));
+        assert!(click_action(&cfg, 2).is_none());
+        assert!(click_action(&cfg, 3).is_none());
+
+        cfg.click_actions.insert("1".to_owned(), "next".to_owned());
+        assert!(matches!(click_action(&cfg, 1), Some(Action::Next)));
+    }
+}
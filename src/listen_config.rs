@@ -0,0 +1,69 @@
+//! This file deals with noticing when the config should be reloaded: whenever one of its' files is modified, or the process receives SIGHUP.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use log::{debug, error};
+
+use crate::structs::event::Event;
+
+/// How often the modification times of the config files are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// This function returns the modification time of the given file; None if it cannot be read (ie while an editor replaces it).
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// This function returns the modification time of every given file.
+fn modified_all(files: &Mutex<Vec<PathBuf>>) -> HashMap<PathBuf, Option<SystemTime>> {
+    let files = files.lock().map(|f| f.clone()).unwrap_or_default();
+    files
+        .into_iter()
+        .map(|path| {
+            let time = modified(&path);
+            (path, time)
+        })
+        .collect()
+}
+
+/// This function spawns a thread which polls the modification times of the config files, and checks for SIGHUP.
+/// Either one makes the main loop reload the config.
+///
+/// Input:
+/// files: the config file in use and the files it includes; replaced by the main loop on every reload.
+/// tx: Sender through which the reload is requested.
+pub fn listen_config(files: Arc<Mutex<Vec<PathBuf>>>, tx: Sender<Event>) {
+    let hangup = Arc::new(AtomicBool::new(false));
+    if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&hangup)) {
+        error!("listen_config: SIGHUP: {e}");
+    }
+
+    thread::spawn(move || {
+        let mut last = modified_all(&files);
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let now = modified_all(&files);
+            // files which were only just included are not a change in themselves.
+            let changed = now
+                .iter()
+                .find(|(path, time)| time.is_some() && last.get(*path).is_some_and(|t| t != *time));
+            if let Some((path, _)) = changed {
+                debug!("listen_config: {} changed", path.display());
+            }
+            let changed = changed.is_some();
+            last = now;
+            if hangup.swap(false, Ordering::Relaxed) || changed {
+                debug!("listen_config: reloading");
+                if tx.send(Event::ConfigChanged).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,135 @@
+//! This file deals with listening for mpris signals on the session bus.
+//! The listener runs on its' own thread with its' own connection, and forwards anything relevant to the main loop.
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use dbus::arg::PropMap;
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use dbus::Message;
+use log::{debug, error, trace, warn};
+
+use crate::structs::event::Event;
+
+/// Bus name prefix shared by all mpris players.
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+/// Object path on which mpris players expose their interfaces.
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+
+/// Properties of the player interface which are shown in player:* fields, or decide which buttons are shown.
+const PLAYER_PROPERTIES: [&str; 6] = ["Volume", "Shuffle", "LoopStatus", "CanGoPrevious", "CanPause", "CanGoNext"];
+
+/// This function returns the unique bus name of whoever sent the given message.
+fn sender(msg: &Message) -> String {
+    msg.sender().map(|s| s.to_string()).unwrap_or_default()
+}
+
+/// This function translates a PropertiesChanged signal into the events relevant to the program.
+/// Properties which are not rendered anywhere are ignored.
+///
+/// Input:
+/// msg: the PropertiesChanged signal.
+/// tx: Sender to forward the events to.
+///
+/// Returns:
+/// false if the main loop hung up (which removes the match), true otherwise.
+fn on_properties_changed(msg: &Message, tx: &Sender<Event>) -> bool {
+    let (interface, changed) = match msg.read2::<&str, PropMap>() {
+        Ok(args) => args,
+        Err(e) => {
+            debug!("on_properties_changed: malformed signal: {e}");
+            return true;
+        }
+    };
+    if interface != "org.mpris.MediaPlayer2.Player" {
+        return true;
+    }
+
+    let mut ok = true;
+    if changed.contains_key("PlaybackStatus") {
+        ok &= tx.send(Event::StatusChanged(sender(msg))).is_ok();
+    }
+    if changed.contains_key("Metadata") {
+        ok &= tx.send(Event::MetadataChanged(sender(msg))).is_ok();
+    }
+    if PLAYER_PROPERTIES.iter().any(|p| changed.contains_key(*p)) {
+        ok &= tx.send(Event::PropertiesChanged(sender(msg))).is_ok();
+    }
+    trace!("on_properties_changed: {:?}", changed.keys());
+    ok
+}
+
+/// This function translates a NameOwnerChanged signal into an event, if it concerns an mpris player.
+///
+/// Input:
+/// msg: the NameOwnerChanged signal.
+/// tx: Sender to forward the event to.
+///
+/// Returns:
+/// false if the main loop hung up (which removes the match), true otherwise.
+fn on_name_owner_changed(msg: &Message, tx: &Sender<Event>) -> bool {
+    match msg.read1::<&str>() {
+        Ok(name) if name.starts_with(MPRIS_PREFIX) => {
+            debug!("on_name_owner_changed: {name}");
+            tx.send(Event::PlayersChanged).is_ok()
+        }
+        _ => true,
+    }
+}
+
+/// This function registers the match rules for all signals the program is interested in.
+///
+/// Input:
+/// conn: Connection to register the matches on.
+/// tx: Sender to forward events to.
+fn add_matches(conn: &Connection, tx: &Sender<Event>) -> Result<(), dbus::Error> {
+    let props = tx.clone();
+    conn.add_match(
+        MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+            .with_path(MPRIS_PATH),
+        move |_: (), _, msg| on_properties_changed(msg, &props),
+    )?;
+
+    let seeked = tx.clone();
+    conn.add_match(
+        MatchRule::new_signal("org.mpris.MediaPlayer2.Player", "Seeked").with_path(MPRIS_PATH),
+        move |_: (), _, msg| seeked.send(Event::Seeked(sender(msg))).is_ok(),
+    )?;
+
+    let owners = tx.clone();
+    conn.add_match(
+        MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged"),
+        move |_: (), _, msg| on_name_owner_changed(msg, &owners),
+    )?;
+    Ok(())
+}
+
+/// This function spawns a thread listening for PropertiesChanged, Seeked and NameOwnerChanged signals.
+/// If the session bus cannot be reached the thread exits, and the main loop falls back to polling.
+///
+/// Input:
+/// tx: Sender through which events are passed to the main loop.
+pub fn listen_events(tx: Sender<Event>) {
+    thread::spawn(move || {
+        let conn = match Connection::new_session() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("listen_events: {e}");
+                return;
+            }
+        };
+        if let Err(e) = add_matches(&conn, &tx) {
+            error!("listen_events: {e}");
+            return;
+        }
+        drop(tx);
+
+        loop {
+            if let Err(e) = conn.process(Duration::from_secs(60)) {
+                warn!("listen_events: {e}; falling back to polling.");
+                return;
+            }
+        }
+    });
+}
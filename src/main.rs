@@ -1,42 +1,231 @@
 //! This file contains all driver code for the program.
+use crate::check_config::check_config;
+use crate::control_socket::{ctl_command, listen_socket, send_action};
+use crate::handle_action::handle_action;
+use crate::listen_clicks::{click_action, listen_clicks};
+use crate::listen_config::listen_config;
+use crate::listen_events::listen_events;
+use crate::migrate_config::migrate_config;
+use crate::override_config::{apply_overrides, env_overrides};
+use crate::register_signals::{register_signals, unregister_signals, SignalActions};
+use crate::resolve_config::{config_path, load_chain};
 use crate::print_players::print_players;
-use crate::print_text::print_text;
+use crate::print_text::{print_header, print_text};
 use crate::update_message::update_message;
+use crate::update_player_fields::update_player_fields;
 use crate::update_players::update_players;
+use crate::update_scroll::{is_scrolling, update_scroll};
+use crate::update_time::{next_time_tick, resample_position, update_time};
 use clap::Parser;
 use core::time;
-use log::{error, info, warn};
+use log::{error, info, trace, warn};
 use mpris::PlayerFinder;
 use std::ffi::OsString;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread;
-use structs::cli::Cli;
-use structs::{config::Config, data::Data};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::{process, thread};
+use std::time::Instant;
+use structs::action::{Action, Reply};
+use structs::cli::{Cli, Command};
+use structs::event::Event;
+use structs::problem::Severity;
+use structs::{
+    config::{Config, Output, CONFIG_VERSION},
+    data::Data,
+};
 
+mod check_config;
+mod control_socket;
+mod handle_action;
+mod listen_clicks;
+mod listen_config;
+mod listen_events;
+mod migrate_config;
+mod override_config;
 mod print_players;
 mod print_text;
+mod register_signals;
+mod resolve_config;
+mod sanitise;
 mod structs;
+mod truncate_text;
 mod update_message;
+mod update_player_fields;
 mod update_players;
+mod update_scroll;
+mod update_time;
 
-/// This function deals with an incoming (USR1) signal.
-/// It is hard-coded to play/pause the active player.
+/// This function performs the action configured for a received signal.
 ///
 /// input:
-/// data: Data struct for active configuration.
-fn handle_signal(data: &Data) {
-    if let Some(p) = &data.current_player {
-        match p.checked_play_pause() {
-            Ok(b) => match b {
-                true => info!("Player play/paused succesfully!"),
-                false => warn!("Failed to send play/pause signal!"),
-            },
-            Err(e) => error!("{e}"),
+/// signal: number of the received signal
+/// signals: all registered signals
+/// pf: PlayerFinder instance for the program
+/// cfg: Configuration of the program
+/// data: mutable Data struct, active state of the program
+/// r: pre-computed rating strings
+fn handle_signal(
+    signal: i32,
+    signals: &SignalActions,
+    pf: &PlayerFinder,
+    cfg: &Config,
+    data: &mut Data,
+    r: &[String],
+) {
+    if let Some(signal) = signals.get(signal) {
+        trace!("handle_signal: received {}", signal.name);
+        handle_action(&signal.action, pf, cfg, data);
+        default_loop(pf, cfg, data, r);
+    }
+}
+
+/// This function sends the given Action to a running instance and prints its' reply.
+///
+/// input:
+/// instance: name of the instance to control, if given on the command line
+/// action: the Action to send
+///
+/// returns:
+/// exit code for the program
+fn run_ctl(instance: Option<&str>, action: &Action) -> i32 {
+    let reply = send_action(instance, action).unwrap_or_else(Reply::err);
+    match serde_json::to_string(&reply) {
+        Ok(json) => println!("{json}"),
+        Err(e) => error!("{e}"),
+    }
+    match reply.ok {
+        true => 0,
+        false => 1,
+    }
+}
+
+/// This function runs the check subcommand: it loads the config file named on the command line, and prints every problem found in it.
+///
+/// input:
+/// cli: parsed command line of the program
+///
+/// returns:
+/// exit code of the process; non-zero if the config could not be loaded or contains errors.
+fn run_check(cli: &Cli) -> i32 {
+    let cfg = match read_config(cli) {
+        Ok((cfg, files)) => {
+            for file in files {
+                println!("checking {}", file.display());
+            }
+            cfg
+        }
+        Err(e) => {
+            println!("error: {e}");
+            return 1;
+        }
+    };
+    let problems = check_config(&cfg);
+    for problem in &problems {
+        println!("{problem}");
+    }
+    let errors = problems
+        .iter()
+        .filter(|p| p.severity == Severity::Error)
+        .count();
+    println!("{errors} error(s), {} warning(s)", problems.len() - errors);
+    match errors {
+        0 => 0,
+        _ => 1,
+    }
+}
+
+/// This function runs the config-path subcommand: it prints every file the config is read from, in the order they are merged.
+///
+/// input:
+/// cli: parsed command line of the program
+///
+/// returns:
+/// exit code of the process; non-zero if the config files could not be found or read.
+fn run_config_path(cli: &Cli) -> i32 {
+    let path = match config_path(&cli.config_file, None) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("error: {e}");
+            return 1;
+        }
+    };
+    if !path.exists() {
+        println!("{} (does not exist yet; it is created with the default config on first use)", path.display());
+        return 0;
+    }
+    match load_chain(&path) {
+        Ok((_, files)) => {
+            for file in files {
+                println!("{}", file.display());
+            }
+            0
+        }
+        Err(e) => {
+            println!("error: {e}");
+            1
         }
     }
 }
 
+/// This function runs the migrate subcommand: it rewrites the config file named on the command line to the current schema.
+/// The original file is kept next to it, with the old schema version and `.bak` appended to its' name.
+///
+/// input:
+/// cli: parsed command line of the program
+///
+/// returns:
+/// exit code of the process; non-zero if the config could not be migrated.
+fn run_migrate(cli: &Cli) -> i32 {
+    let path = match config_path(&cli.config_file, None) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("error: {e}");
+            return 1;
+        }
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("error: {}: {e}", path.display());
+            return 1;
+        }
+    };
+    let migrated = match migrate_config(&text) {
+        Ok(migrated) => migrated,
+        Err(e) => {
+            println!("error: {}: {e}", path.display());
+            return 1;
+        }
+    };
+    if migrated.changes.is_empty() {
+        println!("{} is up to date (version {CONFIG_VERSION})", path.display());
+        return 0;
+    }
+
+    let mut backup = path.clone().into_os_string();
+    backup.push(format!(".v{}.bak", migrated.from));
+    if let Err(e) = fs::copy(&path, &backup) {
+        println!("error: could not back up to {}: {e}", backup.to_string_lossy());
+        return 1;
+    }
+    if let Err(e) = migrated.to_toml().and_then(|text| fs::write(&path, text).map_err(|e| e.to_string())) {
+        println!("error: {}: {e}", path.display());
+        return 1;
+    }
+    for change in &migrated.changes {
+        println!("{change}");
+    }
+    println!(
+        "migrated {} from version {} to {CONFIG_VERSION}; the original is kept at {}",
+        path.display(),
+        migrated.from,
+        backup.to_string_lossy()
+    );
+    0
+}
+
 /// This function contains the default maim loop body of the program.
 /// It updates the active player, updates the output strings based on this, and finally formats and outputs these strings to stdout.
 ///
@@ -45,69 +234,405 @@ fn handle_signal(data: &Data) {
 /// cfg: Configuration of the program
 /// data: mutable Data struct, active state of the program
 /// r: pre-computed rating strings
-fn default_loop(pf: &PlayerFinder, cfg: &Config, data: &mut Data, r: &Vec<String>) {
+fn default_loop(pf: &PlayerFinder, cfg: &Config, data: &mut Data, r: &[String]) {
     update_players(pf, cfg, data);
     update_message(cfg, data, r);
     print_text(cfg, data);
 }
 
+/// This function reacts to a single event received from the session bus.
+/// Only the parts of the state affected by the event are updated.
+///
+/// input:
+/// event: the Event to handle
+/// pf: PlayerFinder instance for the program
+/// cfg: Configuration of the program
+/// data: mutable Data struct, active state of the program
+/// r: pre-computed rating strings
+fn handle_event(event: Event, pf: &PlayerFinder, cfg: &Config, data: &mut Data, r: &[String]) {
+    match event {
+        Event::PlayersChanged => default_loop(pf, cfg, data, r),
+        Event::StatusChanged(sender) => {
+            trace!("handle_event: {sender} changed status");
+            default_loop(pf, cfg, data, r)
+        }
+        Event::MetadataChanged(sender) => {
+            if data.is_current(&sender) || data.other_players.iter().any(|o| o.is_current(&sender)) {
+                update_message(cfg, data, r);
+                print_text(cfg, data);
+            }
+        }
+        Event::PropertiesChanged(sender) => {
+            if data.is_current(&sender) {
+                data.with_config(cfg, update_player_fields);
+                print_text(cfg, data);
+            }
+        }
+        Event::Seeked(sender) => {
+            trace!("handle_event: {sender} seeked");
+            if data.is_current(&sender) {
+                data.with_config(cfg, |active, data| {
+                    resample_position(active, data);
+                    update_time(active, data);
+                });
+                print_text(cfg, data);
+            }
+        }
+        Event::Command(action, reply) => {
+            let _ = reply.send(handle_action(&action, pf, cfg, data));
+            default_loop(pf, cfg, data, r);
+        }
+        Event::Click(button) => {
+            if let Some(action) = click_action(cfg, button) {
+                handle_action(&action, pf, cfg, data);
+                default_loop(pf, cfg, data, r);
+            }
+        }
+        // reloading replaces the config itself, so the main loop takes care of it.
+        Event::ConfigChanged => trace!("handle_event: config changed"),
+        // the registered signals change with the config, so the main loop takes care of them too.
+        Event::Signal(signal) => trace!("handle_event: signal {signal}"),
+    }
+}
+
+/// This function reads the config file named on the command line, merged with the files it includes,
+/// and applies the overrides from the environment and the command line (in that order).
+/// Nothing is written; a missing file is an error.
+///
+/// input:
+/// cli: parsed command line of the program
+///
+/// returns:
+/// the Config and the files it was read from, or a description of why it could not be read.
+fn read_config(cli: &Cli) -> Result<(Config, Vec<PathBuf>), String> {
+    let path = config_path(&cli.config_file, None)?;
+    if !path.exists() {
+        return Err(format!("{}: no such file", path.display()));
+    }
+    let (cfg, files) = load_chain(&path)?;
+    let overrides: Vec<_> = env_overrides()
+        .into_iter()
+        .chain(cli.set.iter().map(|s| ("--set".to_owned(), s.to_owned())))
+        .collect();
+    Ok((apply_overrides(cfg, &overrides)?, files))
+}
+
+/// This function prepares a config for use: templates and player rules are compiled, and the output given on the command line is applied.
+///
+/// input:
+/// cfg: the Config as read from the config files
+/// output: output given on the command line, if any
+///
+/// returns:
+/// the prepared Config, or a description of what is wrong with it.
+fn prepare_config(mut cfg: Config, output: Option<Output>) -> Result<Config, String> {
+    cfg.compile_template()
+        .map_err(|e| format!("invalid format template: {e}"))?;
+    cfg.compile_rules()
+        .map_err(|e| format!("invalid player rule: {e}"))?;
+    if let Some(output) = output {
+        cfg.output = output;
+    }
+    for key in &cfg.unknown_keys {
+        warn!("unknown key {key} is ignored");
+    }
+    if cfg.config_version < CONFIG_VERSION {
+        warn!(
+            "config file is for schema version {}; run the migrate subcommand to upgrade it to {CONFIG_VERSION}",
+            cfg.config_version
+        );
+    }
+    if !cfg.player_prefixes.contains_key("default") {
+        cfg.player_prefixes
+            .insert("default".to_owned(), ">".to_owned());
+    }
+    Ok(cfg)
+}
+
+/// This function creates the config file named on the command line with the default config, if it does not exist yet.
+///
+/// input:
+/// cli: parsed command line of the program
+///
+/// returns:
+/// a description of why the file could not be created, if it could not.
+fn create_config(cli: &Cli) -> Result<(), String> {
+    let path = config_path(&cli.config_file, None)?;
+    if path.exists() {
+        return Ok(());
+    }
+    let text = Config::default().to_toml()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(&path, text).map_err(|e| format!("{}: {e}", path.display()))?;
+    info!("created {} with the default config", path.display());
+    Ok(())
+}
+
+/// This function loads the config file named on the command line, and prepares it for use.
+/// If the file does not exist yet, it is created with the default config first.
+///
+/// input:
+/// cli: parsed command line of the program
+///
+/// returns:
+/// the Config and the files it was read from, or a description of what is wrong with it.
+fn load_config(cli: &Cli) -> Result<(Config, Vec<PathBuf>), String> {
+    create_config(cli)?;
+    let (cfg, files) = read_config(cli)?;
+    Ok((prepare_config(cfg, cli.output)?, files))
+}
+
+/// This function swaps a freshly read config in, along with everything derived from it.
+/// If the config could not be read or prepared, the error is logged and the old config is kept.
+///
+/// input:
+/// read: the Config and the files it was read from, or why it could not be read
+/// output: output given on the command line, if any
+/// cfg: mutable Config of the program
+/// r: mutable pre-computed rating strings
+/// data: mutable Data struct, active state of the program
+///
+/// returns:
+/// the files the new config was read from; None if the old config is kept.
+fn swap_config(
+    read: Result<(Config, Vec<PathBuf>), String>,
+    output: Option<Output>,
+    cfg: &mut Config,
+    r: &mut Vec<String>,
+    data: &mut Data,
+) -> Option<Vec<PathBuf>> {
+    let (mut new, files) = match read.and_then(|(new, files)| Ok((prepare_config(new, output)?, files))) {
+        Ok(new) => new,
+        Err(e) => {
+            error!("failed to reload config, keeping the old one: {e}");
+            return None;
+        }
+    };
+    if new.output != cfg.output {
+        warn!("reload_config: changing the output requires a restart");
+        new.output = cfg.output;
+    }
+
+    *r = new.build_rating_strings();
+    *cfg = new;
+
+    // drop everything computed from the old config.
+    data.effective_config = None;
+    data.field_text.clear();
+    data.scroll.clear();
+    data.other_players.clear();
+    Some(files)
+}
+
+/// This function reloads the config file, and swaps the new config (and everything derived from it) in.
+/// If the new config cannot be loaded, the error is logged and the old config is kept.
+///
+/// input:
+/// cli: parsed command line of the program
+/// cfg: mutable Config of the program
+/// r: mutable pre-computed rating strings
+/// signals: mutable registered signals
+/// tx: Sender through which the reloaded signals are passed to the main loop
+/// watched: config files watched for changes; replaced, as the includes may have changed
+/// data: mutable Data struct, active state of the program
+fn reload_config(
+    cli: &Cli,
+    cfg: &mut Config,
+    r: &mut Vec<String>,
+    signals: &mut SignalActions,
+    tx: &Sender<Event>,
+    watched: &Mutex<Vec<PathBuf>>,
+    data: &mut Data,
+) {
+    let Some(files) = swap_config(read_config(cli), cli.output, cfg, r, data) else {
+        return;
+    };
+    unregister_signals(std::mem::take(signals));
+    *signals = register_signals(cfg, tx.clone());
+    if let Ok(mut watched) = watched.lock() {
+        *watched = files;
+    }
+    info!("reloaded config");
+}
+
 /// Main function. Mostly concerned with initialisation.
 fn main() {
-    // Parse cli flags
+    // Parse cli flags, and read a .env file (if any) for PNP_ variables
     let cli = Cli::parse();
+    let dotenv = dotenvy::dotenv();
 
     // logging initialisation
-    std::env::set_var::<&str, OsString>("RUST_LOG", cli.log_level.into());
+    std::env::set_var::<&str, OsString>("RUST_LOG", cli.log_level.clone().into());
     if let Err(e) = env_logger::init() {
         error!("{e}");
         return;
     }
+    match dotenv {
+        Ok(path) => info!("read {}", path.display()),
+        Err(e) if e.not_found() => (),
+        Err(e) => warn!(".env: {e}"),
+    }
+
+    match &cli.command {
+        Some(Command::Ctl { action }) => process::exit(run_ctl(cli.instance.as_deref(), action)),
+        Some(Command::Check) => process::exit(run_check(&cli)),
+        Some(Command::Migrate) => process::exit(run_migrate(&cli)),
+        Some(Command::ConfigPath) => process::exit(run_config_path(&cli)),
+        None => (),
+    }
 
     // Config, Data, and PlayerFinder initialisation
-    match confy::load::<Config>("polybar-now-playing", cli.config_file.as_str()) {
-        Ok(mut cfg) => {
-            if let None = cfg.player_prefixes.get("default") {
-                cfg.player_prefixes
-                    .insert("default".to_owned(), ">".to_owned());
-            }
+    let (mut cfg, files) = match load_config(&cli) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    let mut data: Data = Data::default();
+    let mut rating_strings = cfg.build_rating_strings();
 
-            let mut data: Data = Data::default();
-            let rating_strings = cfg.build_rating_strings();
+    let pf: PlayerFinder = match PlayerFinder::new() {
+        Ok(finder) => finder,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
 
-            let pf: PlayerFinder;
-            match PlayerFinder::new() {
-                Ok(finder) => pf = finder,
-                Err(e) => {
-                    error!("{e}");
-                    return;
-                }
-            }
+    // event listener initialisation
+    // the sender is kept alive here, so the channel never disconnects and always doubles as a timer.
+    let (tx, rx) = mpsc::channel();
+    let watched = Arc::new(Mutex::new(files));
+    let mut signals = register_signals(&cfg, tx.clone());
+    if !cli.list {
+        listen_events(tx.clone());
+        let instance = cli.instance.clone().unwrap_or_else(|| process::id().to_string());
+        if listen_socket(&instance, tx.clone()) {
+            data.ctl_command = ctl_command(&instance);
+        }
+        if cfg.output == Output::I3bar {
+            listen_clicks(tx.clone());
+        }
+        listen_config(Arc::clone(&watched), tx.clone());
+        print_header(&cfg);
+    }
 
-            // signal interception initialisation
-            let term = Arc::new(AtomicBool::new(false));
-            if let Err(e) =
-                signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&term))
-            {
-                error!("{e}");
-                return;
+    // main body loop
+    // None until the first poll, which happens straight away.
+    let mut last_poll: Option<Instant> = None;
+    let mut last_scroll = Instant::now();
+    loop {
+        let poll_delay = time::Duration::from_millis(cfg.update_delay);
+        if cli.list {
+            thread::sleep(poll_delay);
+            print_players(&pf, &cfg);
+        } else {
+            let scroll_step = time::Duration::from_millis(cfg.scroll_step);
+            let scrolling = is_scrolling(&cfg, &data);
+            let until_poll = |last_poll: Option<Instant>| {
+                last_poll.map_or(time::Duration::ZERO, |t| poll_delay.saturating_sub(t.elapsed()))
+            };
+            let mut timeout = until_poll(last_poll);
+            if scrolling {
+                timeout = timeout.min(scroll_step.saturating_sub(last_scroll.elapsed()));
+            }
+            if let Some(tick) = next_time_tick(&cfg, &data) {
+                timeout = timeout.min(tick);
             }
 
-            // main body loop
-            loop {
-                thread::sleep(time::Duration::from_millis(cfg.update_delay));
-                match cli.list {
-                    true => print_players(&pf),
-                    false => default_loop(&pf, &cfg, &mut data, &rating_strings),
+            match rx.recv_timeout(timeout) {
+                Ok(Event::ConfigChanged) => {
+                    reload_config(&cli, &mut cfg, &mut rating_strings, &mut signals, &tx, &watched, &mut data);
+                    default_loop(&pf, &cfg, &mut data, &rating_strings);
                 }
-
-                if term.load(Ordering::Relaxed) {
-                    handle_signal(&data);
-                    term.swap(false, Ordering::Relaxed);
-                };
+                Ok(Event::Signal(signal)) => handle_signal(signal, &signals, &pf, &cfg, &mut data, &rating_strings),
+                Ok(event) => handle_event(event, &pf, &cfg, &mut data, &rating_strings),
+                Err(RecvTimeoutError::Timeout) => {
+                    if until_poll(last_poll).is_zero() {
+                        default_loop(&pf, &cfg, &mut data, &rating_strings);
+                        last_poll = Some(Instant::now());
+                    }
+                    if scrolling && last_scroll.elapsed() >= scroll_step {
+                        update_scroll(&cfg, &mut data);
+                        last_scroll = Instant::now();
+                    }
+                    data.with_config(&cfg, update_time);
+                    print_text(&cfg, &mut data);
+                }
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
             }
         }
-        Err(e) => {
-            error!("{e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::{read_config, swap_config};
+    use crate::structs::cli::Cli;
+    use crate::structs::config::{Config, Output, Rating};
+    use crate::structs::data::Data;
+
+    #[test]
+    fn reloading_swaps_the_config_in() {
+        let mut cfg = Config::default();
+        let mut r = cfg.build_rating_strings();
+        let mut data = Data::default();
+        data.field_text.insert("xesam:title".to_owned(), "Allegro".to_owned());
+
+        let mut new = Config {
+            format: Some("{xesam:artist} - {xesam:title}".to_owned()),
+            rating_icons: Some(Rating {
+                nil: '.',
+                half: '+',
+                full: '*',
+            }),
+            output: Output::I3bar,
+            ..Default::default()
+        };
+        new.click_actions.insert("2".to_owned(), "next".to_owned());
+        let files = swap_config(Ok((new, vec![PathBuf::from("a.toml")])), None, &mut cfg, &mut r, &mut data);
+        assert_eq!(files, Some(vec![PathBuf::from("a.toml")]));
+        assert!(cfg.template.is_some());
+        assert_eq!(r, cfg.build_rating_strings());
+        assert!(r[10].starts_with('*'), "{:?}", r);
+        assert_eq!(cfg.click_actions["2"], "next");
+        assert!(cfg.output == Output::Polybar);
+        assert!(data.field_text.is_empty());
+    }
+
+    #[test]
+    fn reloading_keeps_the_old_config_on_errors() {
+        let mut cfg = Config::default();
+        let mut r = cfg.build_rating_strings();
+        let mut data = Data::default();
+        data.field_text.insert("xesam:title".to_owned(), "Allegro".to_owned());
+
+        let bad = Config {
+            format: Some("{xesam:title".to_owned()),
+            ..Default::default()
+        };
+        for read in [Err("expected a value".to_owned()), Ok((bad, Vec::new()))] {
+            assert_eq!(swap_config(read, None, &mut cfg, &mut r, &mut data), None);
+            assert!(cfg.format.is_none());
+            assert_eq!(data.field_text["xesam:title"], "Allegro");
         }
-    };
+    }
+
+    #[test]
+    fn reading_a_missing_config_creates_nothing() {
+        let path = std::env::temp_dir().join(format!("pnp-missing-{}.toml", std::process::id()));
+        let cli = Cli::parse_from(["polybar-now-playing-rust", "--config", path.to_str().unwrap(), "check"]);
+        match read_config(&cli) {
+            Err(e) => assert!(e.contains("no such file"), "{e}"),
+            Ok(_) => panic!("a missing config was read"),
+        }
+        assert!(!path.exists());
+    }
 }
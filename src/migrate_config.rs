@@ -0,0 +1,316 @@
+//! This file contains the migration of config files written for an older version of the schema to the current one.
+//! Every version which renames or removes something adds a step to MIGRATIONS; the steps are applied in order.
+use toml::value::Table;
+use toml::Value;
+
+use crate::structs::config::{Config, CONFIG_VERSION};
+
+/// This struct describes what changed between one schema version and the previous one.
+struct Migration {
+    /// The version this step migrates to.
+    version: u32,
+    /// Top-level keys which were renamed, as (old, new).
+    renamed_keys: &'static [(&'static str, &'static str)],
+    /// Actions which were renamed, as (old, new). Applied to click_actions, signal_actions and the click key of Fields.
+    renamed_actions: &'static [(&'static str, &'static str)],
+    /// Changes which are more than a rename, applied after the renamed keys.
+    convert: Option<fn(&mut Table, &mut Vec<String>)>,
+}
+
+/// All migration steps, in order of version.
+const MIGRATIONS: [Migration; 2] = [
+    Migration {
+        version: 2,
+        renamed_keys: &[],
+        renamed_actions: &[("cycle-player", "cycle-next")],
+        convert: None,
+    },
+    Migration {
+        version: 3,
+        renamed_keys: &[],
+        renamed_actions: &[],
+        convert: Some(fuzzy_to_truncate),
+    },
+];
+
+/// This function replaces the fuzzy flag (of the config and of each player override) by the truncate mode of the Fields it applied to.
+/// Fuzzy cutoff became the 'word' mode; Fields which already have a truncate mode keep it.
+///
+/// Input:
+/// table: the top-level table of the config file.
+/// changes: list of changes to add to.
+fn fuzzy_to_truncate(table: &mut Table, changes: &mut Vec<String>) {
+    /// This function applies the fuzzy flag of one table (or the inherited one, if it has none) to its' Fields.
+    fn convert(location: &str, table: &mut Table, keys: &[&str], inherited: bool, changes: &mut Vec<String>) {
+        let fuzzy = match table.remove("fuzzy") {
+            Some(fuzzy) => {
+                changes.push(format!("{location}fuzzy: removed; fuzzy cutoff is now truncate = \"word\" on each field"));
+                fuzzy.as_bool() == Some(true)
+            }
+            None => inherited,
+        };
+        if !fuzzy {
+            return;
+        }
+        for key in keys {
+            let Some(Value::Array(fields)) = table.get_mut(*key) else {
+                continue;
+            };
+            for (i, field) in fields.iter_mut().enumerate() {
+                if let Some(field) = field.as_table_mut().filter(|f| !f.contains_key("truncate")) {
+                    field.insert("truncate".to_owned(), Value::String("word".to_owned()));
+                    changes.push(format!("{location}{key}[{i}]: set truncate to \"word\""));
+                }
+            }
+        }
+    }
+
+    let global = table.get("fuzzy").and_then(Value::as_bool) == Some(true);
+    convert("", table, &["metadata_fields", "alt_metadata_fields"], false, changes);
+    if let Some(Value::Table(overrides)) = table.get_mut("player_overrides") {
+        for (identity, o) in overrides.iter_mut() {
+            if let Some(o) = o.as_table_mut() {
+                let location = format!("player_overrides.{identity}.");
+                convert(&location, o, &["metadata_fields"], global, changes);
+            }
+        }
+    }
+}
+
+/// This struct contains the result of migrating a config file.
+pub struct Migrated {
+    /// Schema version the file was written for.
+    pub from: u32,
+    /// The config, in the current schema.
+    pub cfg: Config,
+    /// Human readable description of every change made.
+    pub changes: Vec<String>,
+    /// Keys to write back, if the file includes others; None if all keys are written.
+    /// Adding defaults to such a file would hide the values of the included files.
+    keys: Option<Vec<String>>,
+}
+
+impl Migrated {
+    /// This function serialises the migrated config, as written back to the config file.
+    pub fn to_toml(&self) -> Result<String, String> {
+        let Some(keys) = &self.keys else {
+            return self.cfg.to_toml();
+        };
+        let mut value = Value::try_from(&self.cfg).map_err(|e| e.to_string())?;
+        if let Value::Table(table) = &mut value {
+            *table = std::mem::take(table)
+                .into_iter()
+                .filter(|(key, _)| keys.contains(key))
+                .collect();
+        }
+        toml::to_string_pretty(&value).map_err(|e| e.to_string())
+    }
+}
+
+/// This function renames an action, if the command (ie the first word) of the given action is the old name.
+///
+/// Input:
+/// location: path of the key holding the action, used in the reported change.
+/// value: the action as written in the config file.
+/// renamed: list of (old, new) action names.
+/// changes: list of changes to add to.
+fn rename_action(location: &str, value: &mut Value, renamed: &[(&str, &str)], changes: &mut Vec<String>) {
+    let Some(action) = value.as_str() else {
+        return;
+    };
+    let command = action.split_whitespace().next().unwrap_or_default();
+    if let Some((old, new)) = renamed.iter().find(|(old, _)| *old == command) {
+        let args = action.trim_start().trim_start_matches(old);
+        changes.push(format!("{location}: renamed action \"{old}\" to \"{new}\""));
+        *value = Value::String(format!("{new}{args}"));
+    }
+}
+
+/// This function applies one migration step to the parsed config file.
+///
+/// Input:
+/// table: the top-level table of the config file.
+/// step: the migration to apply.
+/// changes: list of changes to add to.
+fn apply(table: &mut Table, step: &Migration, changes: &mut Vec<String>) {
+    for (old, new) in step.renamed_keys {
+        if let Some(value) = table.remove(*old) {
+            changes.push(format!("renamed key \"{old}\" to \"{new}\""));
+            table.insert((*new).to_owned(), value);
+        }
+    }
+    if let Some(convert) = step.convert {
+        convert(table, changes);
+    }
+    if step.renamed_actions.is_empty() {
+        return;
+    }
+    for key in ["click_actions", "signal_actions"] {
+        if let Some(Value::Table(actions)) = table.get_mut(key) {
+            for (name, value) in actions.iter_mut() {
+                rename_action(&format!("{key}.{name}"), value, step.renamed_actions, changes);
+            }
+        }
+    }
+    for key in ["metadata_fields", "alt_metadata_fields"] {
+        if let Some(Value::Array(fields)) = table.get_mut(key) {
+            for (i, field) in fields.iter_mut().enumerate() {
+                if let Some(value) = field.get_mut("click") {
+                    rename_action(&format!("{key}[{i}].click"), value, step.renamed_actions, changes);
+                }
+            }
+        }
+    }
+}
+
+/// This function migrates the contents of a config file to the current schema.
+/// Keys which are no longer known are dropped, and keys which are missing are added with their default value,
+/// unless the file includes others (as those may set them).
+///
+/// Input:
+/// text: contents of the config file.
+///
+/// Returns:
+/// the migrated config along with every change made, or a description of why the file cannot be migrated.
+pub fn migrate_config(text: &str) -> Result<Migrated, String> {
+    let mut table = match text.parse::<Value>().map_err(|e| e.to_string())? {
+        Value::Table(table) => table,
+        _ => return Err("the config file is not a table".to_owned()),
+    };
+    let from = match table.get("config_version") {
+        Some(Value::Integer(v)) => u32::try_from(*v).map_err(|e| format!("config_version: {e}"))?,
+        Some(_) => return Err("config_version: expected an integer".to_owned()),
+        None => 1,
+    };
+    if from > CONFIG_VERSION {
+        return Err(format!(
+            "the config file is for schema version {from}, but this version of the program only knows up to {CONFIG_VERSION}"
+        ));
+    }
+
+    let mut changes = Vec::new();
+    for step in MIGRATIONS.iter().filter(|step| step.version > from) {
+        apply(&mut table, step, &mut changes);
+    }
+    table.insert("config_version".to_owned(), Value::Integer(CONFIG_VERSION.into()));
+
+    let old_keys: Vec<String> = table.keys().cloned().collect();
+    let cfg: Config = serde_ignored::deserialize(Value::Table(table), |path| {
+        changes.push(format!("removed unknown key \"{path}\""));
+    })
+    .map_err(|e| e.to_string())?;
+
+    let keys = match cfg.include.is_empty() {
+        true => None,
+        false => Some(old_keys.clone()),
+    };
+    if let (None, Ok(Value::Table(new))) = (&keys, Value::try_from(&cfg)) {
+        for key in new.keys().filter(|k| !old_keys.contains(k)) {
+            changes.push(format!("added key \"{key}\" with its default value"));
+        }
+    }
+    if from < CONFIG_VERSION {
+        changes.push(format!("set config_version from {from} to {CONFIG_VERSION}"));
+    }
+    Ok(Migrated {
+        from,
+        cfg,
+        changes,
+        keys,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::migrate_config;
+    use crate::structs::config::{TruncateMode, CONFIG_VERSION};
+
+    #[test]
+    fn old_files_are_upgraded() {
+        let text = r#"
+            hide_output = false
+            colour = "red"
+
+            [click_actions]
+            2 = "cycle-player"
+            3 = "seek -5"
+
+            [[metadata_fields]]
+            field = "xesam:title"
+            num_chars = 30
+            format = "{}"
+            click = "cycle-player"
+        "#;
+        let migrated = migrate_config(text).unwrap();
+        assert_eq!(migrated.from, 1);
+        assert_eq!(migrated.cfg.config_version, CONFIG_VERSION);
+        assert!(!migrated.cfg.hide_output);
+        assert_eq!(migrated.cfg.click_actions["2"], "cycle-next");
+        assert_eq!(migrated.cfg.click_actions["3"], "seek -5");
+        assert_eq!(migrated.cfg.metadata_fields[0].click.as_deref(), Some("cycle-next"));
+        for change in [
+            "click_actions.2: renamed action \"cycle-player\" to \"cycle-next\"",
+            "metadata_fields[0].click: renamed action \"cycle-player\" to \"cycle-next\"",
+            "removed unknown key \"colour\"",
+            "added key \"update_delay\" with its default value",
+        ] {
+            assert!(migrated.changes.iter().any(|c| c == change), "{change}");
+        }
+    }
+
+    #[test]
+    fn current_files_are_unchanged() {
+        let text = crate::structs::config::Config::default().to_toml().unwrap();
+        let migrated = migrate_config(&text).unwrap();
+        assert_eq!(migrated.from, CONFIG_VERSION);
+        assert!(migrated.changes.is_empty(), "{:?}", migrated.changes);
+    }
+
+    #[test]
+    fn fuzzy_becomes_word_truncation() {
+        let text = r#"
+            config_version = 2
+            fuzzy = true
+
+            [[metadata_fields]]
+            field = "xesam:title"
+            num_chars = 30
+            format = "{}"
+
+            [[metadata_fields]]
+            field = "xesam:artist"
+            num_chars = 20
+            format = "{}"
+            truncate = "start"
+
+            [player_overrides.mpv]
+            fuzzy = false
+            metadata_fields = [{ field = "xesam:title", num_chars = 10, format = "{}" }]
+
+            [player_overrides.Spotify]
+            metadata_fields = [{ field = "xesam:title", num_chars = 10, format = "{}" }]
+        "#;
+        let migrated = migrate_config(text).unwrap();
+        let cfg = &migrated.cfg;
+        assert_eq!(cfg.metadata_fields[0].truncate, TruncateMode::Word);
+        assert_eq!(cfg.metadata_fields[1].truncate, TruncateMode::Start);
+        let fields = |identity: &str| cfg.player_overrides[identity].metadata_fields.clone().unwrap();
+        assert_eq!(fields("mpv")[0].truncate, TruncateMode::End);
+        assert_eq!(fields("Spotify")[0].truncate, TruncateMode::Word);
+        assert!(migrated.changes.contains(&"player_overrides.mpv.fuzzy: removed; fuzzy cutoff is now truncate = \"word\" on each field".to_owned()));
+        assert!(!migrated.changes.iter().any(|c| c.starts_with("removed unknown key")));
+    }
+
+    #[test]
+    fn files_with_includes_get_no_defaults() {
+        let migrated = migrate_config("include = ['base.toml']\nhide_output = false\n").unwrap();
+        assert_eq!(migrated.changes, vec![format!("set config_version from 1 to {CONFIG_VERSION}")]);
+        let text = migrated.to_toml().unwrap();
+        assert_eq!(text.lines().count(), 3, "{text}");
+    }
+
+    #[test]
+    fn newer_files_are_refused() {
+        assert!(migrate_config(&format!("config_version = {}", CONFIG_VERSION + 1)).is_err());
+    }
+}
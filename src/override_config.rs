@@ -0,0 +1,280 @@
+//! This file deals with overriding single config keys from the command line (`--set key=value`) and the environment (`PNP_KEY=value`).
+//!
+//! Keys are written as paths into the config: `update_delay`, `rating_icons.full`, `metadata_fields[0].num_chars` or
+//! `player_priorities."VLC Media Player"`. In environment variables, `__` separates the parts and a number is an index:
+//! `PNP_METADATA_FIELDS__0__NUM_CHARS`. Values are written as in TOML; anything which is not valid TOML is taken as a string.
+use std::env;
+
+use toml::value::Table;
+use toml::Value;
+
+use crate::structs::config::Config;
+
+/// Prefix of environment variables which override config keys.
+pub const ENV_PREFIX: &str = "PNP_";
+
+/// This enum represents one part of the path to a config key.
+#[derive(Debug, PartialEq)]
+enum Segment {
+    /// Key of a table.
+    Key(String),
+    /// Index into an array.
+    Index(usize),
+}
+
+/// This function parses the path to a config key.
+///
+/// Input:
+/// key: the path, ie `metadata_fields[0].num_chars`.
+///
+/// Returns:
+/// the parts of the path, or a description of why it is malformed.
+fn parse_key(key: &str) -> Result<Vec<Segment>, String> {
+    let mut out = Vec::new();
+    let mut rest = key;
+    loop {
+        let name;
+        (name, rest) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted
+                .split_once('"')
+                .ok_or_else(|| format!("unterminated quote in {key}"))?,
+            None => rest.split_at(rest.find(['.', '[']).unwrap_or(rest.len())),
+        };
+        if name.is_empty() {
+            return Err(format!("empty key in {key}"));
+        }
+        out.push(Segment::Key(name.to_owned()));
+
+        while let Some(index) = rest.strip_prefix('[') {
+            let (index, after) = index
+                .split_once(']')
+                .ok_or_else(|| format!("unterminated index in {key}"))?;
+            let index = index
+                .parse::<usize>()
+                .map_err(|e| format!("invalid index {index} in {key}: {e}"))?;
+            out.push(Segment::Index(index));
+            rest = after;
+        }
+        match rest.strip_prefix('.') {
+            Some(after) => rest = after,
+            None if rest.is_empty() => return Ok(out),
+            None => return Err(format!("expected '.' or '[' before {rest} in {key}")),
+        }
+    }
+}
+
+/// This function sets the value at the given path, creating tables along the way where needed.
+///
+/// Input:
+/// value: the (part of the) config to set the value in.
+/// path: remaining path to the key.
+/// new: the value to set.
+fn set(value: &mut Value, path: &[Segment], new: Value) -> Result<(), String> {
+    let Some((first, rest)) = path.split_first() else {
+        *value = new;
+        return Ok(());
+    };
+    match (first, value) {
+        (Segment::Key(key), Value::Table(table)) => {
+            let next = table
+                .entry(key.as_str())
+                .or_insert_with(|| Value::Table(Table::new()));
+            set(next, rest, new)
+        }
+        (Segment::Index(i), Value::Array(array)) => {
+            let len = array.len();
+            let next = array
+                .get_mut(*i)
+                .ok_or_else(|| format!("index {i} is out of range ({len} entries)"))?;
+            set(next, rest, new)
+        }
+        (Segment::Key(key), _) => Err(format!("cannot set {key}: not a table")),
+        (Segment::Index(i), _) => Err(format!("cannot set [{i}]: not an array")),
+    }
+}
+
+/// This function parses the value of an override. TOML values are taken as such; anything else is a string, kept exactly as given.
+fn parse_value(raw: &str) -> Value {
+    match format!("v = {raw}").parse::<Value>() {
+        Ok(Value::Table(mut table)) if table.len() == 1 => table.remove("v"),
+        _ => None,
+    }
+    .unwrap_or_else(|| Value::String(raw.to_owned()))
+}
+
+/// This function converts the config, as serialised to a Value, back into a Config.
+/// Unlike when reading a config file, unknown keys are an error: they can only come from a mistyped override.
+fn to_config(value: Value) -> Result<Config, String> {
+    let mut unknown = Vec::new();
+    let cfg = serde_ignored::deserialize(value, |path| unknown.push(path.to_string()))
+        .map_err(|e| e.to_string())?;
+    match unknown.first() {
+        Some(path) => Err(format!("unknown key {path}")),
+        None => Ok(cfg),
+    }
+}
+
+/// This function applies a single override to the config.
+/// Whitespace around the key is ignored, but the value is taken as given, as whitespace may be part of a string (ie metadata_separator).
+///
+/// Input:
+/// cfg: the config to override a key of.
+/// assignment: the override, as `key=value`.
+///
+/// Returns:
+/// the overridden config, or a description of why the key or value is invalid.
+fn apply_override(cfg: &Config, assignment: &str) -> Result<Config, String> {
+    let (key, raw) = assignment
+        .split_once('=')
+        .ok_or_else(|| "expected key=value".to_owned())?;
+    let path = parse_key(key.trim())?;
+    let base = Value::try_from(cfg).map_err(|e| e.to_string())?;
+
+    let mut value = base.clone();
+    let parsed = parse_value(raw);
+    let is_string = parsed.is_str();
+    set(&mut value, &path, parsed)?;
+    match to_config(value) {
+        Ok(cfg) => Ok(cfg),
+        // a value like 5 or true may still be meant as a string, ie for pin_icon.
+        Err(e) if !is_string => {
+            let mut value = base;
+            set(&mut value, &path, Value::String(raw.to_owned()))?;
+            to_config(value).map_err(|_| e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// This function converts the name of an environment variable to the key it overrides.
+/// Every part is lowercased, so keys containing uppercase letters (ie player identities) can only be overridden with --set.
+///
+/// Input:
+/// name: name of the variable, ie `PNP_METADATA_FIELDS__0__NUM_CHARS`.
+///
+/// Returns:
+/// the key, ie `metadata_fields[0].num_chars`, or None if the variable is not an override.
+fn env_key(name: &str) -> Option<String> {
+    let name = name.strip_prefix(ENV_PREFIX).filter(|n| !n.is_empty())?;
+    let mut key = String::new();
+    for part in name.split("__") {
+        match part.parse::<usize>() {
+            Ok(index) => key.push_str(&format!("[{index}]")),
+            Err(_) if key.is_empty() => key.push_str(&part.to_lowercase()),
+            Err(_) => key.push_str(&format!(".{}", part.to_lowercase())),
+        }
+    }
+    Some(key)
+}
+
+/// This function collects the overrides set in the environment, sorted by name.
+///
+/// Returns:
+/// Vec of (name of the variable, `key=value`).
+pub fn env_overrides() -> Vec<(String, String)> {
+    let mut out: Vec<_> = env::vars()
+        .filter_map(|(name, value)| Some((env_key(&name)?, name, value)))
+        .map(|(key, name, value)| (name, format!("{key}={value}")))
+        .collect();
+    out.sort();
+    out
+}
+
+/// This function applies overrides to the config, in order; later overrides of the same key win.
+///
+/// Input:
+/// cfg: the config as read from the config files.
+/// overrides: Vec of (where the override came from, `key=value`).
+///
+/// Returns:
+/// the overridden config, or a description of the first invalid override.
+pub fn apply_overrides(mut cfg: Config, overrides: &[(String, String)]) -> Result<Config, String> {
+    // unknown keys are not serialized, so they are carried over by hand.
+    let unknown_keys = std::mem::take(&mut cfg.unknown_keys);
+    for (source, assignment) in overrides {
+        cfg = apply_override(&cfg, assignment).map_err(|e| format!("{source} {assignment}: {e}"))?;
+    }
+    cfg.unknown_keys = unknown_keys;
+    Ok(cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_overrides, env_key, parse_key, Segment};
+    use crate::structs::config::Config;
+
+    fn set(assignments: &[&str]) -> Result<Config, String> {
+        let overrides: Vec<_> = assignments
+            .iter()
+            .map(|a| ("--set".to_owned(), a.to_string()))
+            .collect();
+        apply_overrides(Config::default(), &overrides)
+    }
+
+    #[test]
+    fn keys_are_parsed_into_paths() {
+        assert_eq!(
+            parse_key("metadata_fields[0].num_chars").unwrap(),
+            vec![
+                Segment::Key("metadata_fields".to_owned()),
+                Segment::Index(0),
+                Segment::Key("num_chars".to_owned())
+            ]
+        );
+        assert_eq!(
+            parse_key("player_priorities.\"VLC Media Player\"").unwrap(),
+            vec![
+                Segment::Key("player_priorities".to_owned()),
+                Segment::Key("VLC Media Player".to_owned())
+            ]
+        );
+        assert!(parse_key("a..b").is_err());
+        assert!(parse_key("a[x]").is_err());
+        assert!(parse_key("a[0]b").is_err());
+    }
+
+    #[test]
+    fn overrides_are_type_checked() {
+        let cfg = set(&[
+            "update_delay=1000",
+            "metadata_fields[0].num_chars=25",
+            "metadata_separator= / ",
+            "pin_icon=5",
+            "player_priorities.\"VLC Media Player\"=1",
+            "rating_icons.full='*'",
+        ])
+        .unwrap();
+        assert_eq!(cfg.update_delay, 1000);
+        assert_eq!(cfg.metadata_fields[0].num_chars, 25);
+        assert_eq!(cfg.metadata_separator, " / ");
+        assert_eq!(cfg.pin_icon, "5");
+        assert_eq!(cfg.player_priorities["VLC Media Player"], 1);
+        assert_eq!(cfg.rating_icons.unwrap().full, '*');
+
+        for bad in [
+            "update_delay=soon",
+            "update_dely=1000",
+            "metadata_fields[5].num_chars=25",
+            "metadata_fields[0].num_char=25",
+            "update_delay",
+        ] {
+            let e = set(&[bad]).err().unwrap();
+            assert!(e.starts_with(&format!("--set {bad}: ")), "{e}");
+        }
+        assert!(set(&["update_dely=1000"]).err().unwrap().ends_with("unknown key update_dely"));
+    }
+
+    #[test]
+    fn environment_names_map_to_keys() {
+        assert_eq!(env_key("PNP_UPDATE_DELAY").as_deref(), Some("update_delay"));
+        assert_eq!(
+            env_key("PNP_METADATA_FIELDS__0__NUM_CHARS").as_deref(),
+            Some("metadata_fields[0].num_chars")
+        );
+        assert_eq!(env_key("PNP_RATING_ICONS__FULL").as_deref(), Some("rating_icons.full"));
+        // every part is lowercased, so identities like Spotify cannot be written.
+        assert_eq!(env_key("PNP_PLAYER_PRIORITIES__Spotify").as_deref(), Some("player_priorities.spotify"));
+        assert_eq!(env_key("PNP_"), None);
+        assert_eq!(env_key("HOME"), None);
+    }
+}
@@ -2,20 +2,35 @@
 use log::error;
 use mpris::PlayerFinder;
 
+use crate::structs::{config::Config, player_rule::PlayerInfo};
 
-/// This function finds and prints the identities of all players on the system to stdout.
-/// It is intended to help people find the right identities to use in their configuration files.
-pub fn print_players(pf: &PlayerFinder) {
+/// This function finds and prints the identities of all players on the system to stdout, along with the rules they match.
+/// It is intended to help people find the right identities and rules to use in their configuration files.
+pub fn print_players(pf: &PlayerFinder, cfg: &Config) {
     match pf.find_all() {
         Ok(players) => {
             if players.is_empty() {
                 println!("No players found!");
             } else {
                 for player in players {
-                    println!("{}", player.identity());
+                    let info = PlayerInfo::new(&player, true);
+                    let priority = match cfg.match_priority(&info) {
+                        Some((rule, idx)) => format!("{idx} (rule '{}')", rule.key),
+                        None => "none".to_owned(),
+                    };
+                    let prefix = match cfg.match_prefix(&info) {
+                        Some((rule, prefix)) => format!("{prefix} (rule '{}')", rule.key),
+                        None => "default".to_owned(),
+                    };
+                    println!(
+                        "{}\n  bus name: {}\n  desktop entry: {}\n  priority: {priority}\n  prefix: {prefix}",
+                        info.identity,
+                        info.bus_name,
+                        info.desktop_entry.as_deref().unwrap_or("none"),
+                    );
                 }
             }
         },
         Err(e) => error!("{e}"),
     }
-}
\ No newline at end of file
+}
@@ -1,66 +1,41 @@
 //! This file deals with formatting and outputting to stdout.
 use log::{error, info};
+use mpris::PlaybackStatus;
 use std::collections::HashMap;
 use string_builder::Builder;
 
+use crate::sanitise::{escape_markup, sanitise, should_escape};
 use crate::structs::{
-    config::{Config, Field},
-    data::Data,
+    config::{Config, Field, Output, Overflow, TruncateMode},
+    data::{Data, PositionSample, ScrollState},
+    output::{I3barBlock, I3barHeader, WaybarOutput},
+    template::{Node, Placeholder},
 };
-
-/// This function finds the last whitespace in a string and returns its' index.
-/// If there is no whitespace it returns usize::MAX instead.
-fn fuzzy_cutoff(str: &str) -> usize {
-    str.rfind(char::is_whitespace).unwrap_or_else(|| usize::MAX)
-}
-
-/// This function helps deal with non-UTF8 strings.
-/// It returns the nearest character boundary from the given usize.
-/// Note it only looks before the max_len indicated index (that is, it never returns a value > max_len).
-///
-/// Input:
-/// str: string to check
-/// max_len: usize to check if it's a boundary or not
-///
-/// Returns:
-/// usize indicatheing the index of the character boundary nearest max_len.
-fn get_char_boundary(str: &str, max_len: usize) -> usize {
-    match max_len > str.len() || str.is_char_boundary(max_len) {
-        true => max_len,
-        false => {
-            let mut idx = max_len;
-            while !str.is_char_boundary(idx) {
-                idx -= 1;
-            }
-            idx
-        }
-    }
-}
+use crate::truncate_text::{display_width, scroll_window, truncate};
 
 /// This function applies truncation to each string in the given hashmap, as dictated by the values in the given Fields.
-/// It also applies fuzzy cutoff if the configuration option for this is enabled.
+/// Fields configured to scroll are cut to their current scroll window instead.
 ///
 /// Input:
-/// field: Vec of Fields, necessary to know where to truncate each string.
-/// brk: Optional, character to insert when a string is truncated.
-/// fuzzy: Whether to apply the fuzzy truncation function or not.
+/// field: Vec of Fields, necessary to know where (and how) to truncate each string.
+/// brk: Optional, string to insert where a string is truncated.
+/// scroll: Hashmap containing the scroll position of each scrolling field.
 /// strings: Hashmap containing the strings to be truncated. Key values should match with the names of the Fields Vec.
 fn cutoff(
-    fields: &Vec<Field>,
-    brk: Option<char>,
-    fuzzy: bool,
+    fields: &[Field],
+    brk: Option<&str>,
+    scroll: &HashMap<String, ScrollState>,
     strings: &mut HashMap<String, String>,
 ) {
     for field in fields {
         if let Some(str) = strings.get_mut(&field.field) {
-            if str.len() >= field.num_chars as usize {
-                str.truncate(get_char_boundary(str, field.num_chars as usize));
-                if fuzzy {
-                    str.truncate(fuzzy_cutoff(str))
-                }
-                if let Some(c) = brk {
-                    str.push(c);
+            if field.overflow == Overflow::Scroll {
+                if display_width(str) > field.num_chars as usize {
+                    let offset = scroll.get(&field.field).map_or(0, |s| s.offset);
+                    *str = scroll_window(str, &field.scroll_gap, field.num_chars as usize, offset);
                 }
+            } else {
+                truncate(str, field.num_chars as usize, brk, field.truncate);
             }
         }
     }
@@ -70,40 +45,104 @@ fn cutoff(
 ///
 /// Input:
 /// b: mutable String builder to append to.
+/// cfg: Config struct for the program.
 /// data: Data struct containing the current prefix character.
-fn append_prefix(b: &mut Builder, data: &Data) {
-    b.append(data.prefix.to_owned());
+fn append_prefix(b: &mut Builder, cfg: &Config, data: &Data) {
+    match should_escape(cfg) {
+        true => b.append(escape_markup(&data.prefix)),
+        false => b.append(data.prefix.to_owned()),
+    }
     b.append("  ");
 }
 
+/// This function wraps a label in a polybar action tag, which sends the given action to this instance when left-clicked.
+///
+/// Input:
+/// ctl: shell command sending an action to this instance, minus the action.
+/// action: the action to send, as written for the ctl subcommand.
+/// label: the text to make clickable.
+fn action_tag(ctl: &str, action: &str, label: &str) -> String {
+    let cmd = format!("{ctl} {action}").replace(':', "\\:");
+    format!("%{{A1:{cmd}:}}{label}%{{A}}")
+}
+
+/// This function applies the format (and escaping for the output) of a field to its' output string.
+/// For polybar output, fields with a click action are wrapped in an action tag.
+///
+/// Input:
+/// cfg: Config struct for the program.
+/// data: Data struct containing the command to send actions with.
+/// field: the Field the string belongs to.
+/// string: the (truncated) output string of the field.
+fn format_field(cfg: &Config, data: &Data, field: &Field, string: &str) -> String {
+    let out = field.format.replace("{}", &sanitise(cfg, string));
+    match (cfg.output, &field.click, &data.ctl_command) {
+        (Output::Polybar, Some(action), Some(ctl)) => action_tag(ctl, action, &out),
+        _ => out,
+    }
+}
+
+/// This function appends the clickable previous, play/pause and next buttons to the given string builder.
+/// Buttons are only rendered for polybar output, and only if the active player supports them.
+/// What the player supports is cached in Data by update_player_fields, so the player is not queried here.
+///
+/// Input:
+/// b: mutable String builder to append to.
+/// cfg: Config struct for the program, containing the button labels.
+/// data: Data struct containing the capabilities of the active player and the command to send actions with.
+fn append_buttons(b: &mut Builder, cfg: &Config, data: &Data) {
+    let (Output::Polybar, Some(buttons), Some(ctl)) = (cfg.output, &cfg.buttons, &data.ctl_command) else {
+        return;
+    };
+
+    let mut tags = Vec::new();
+    if data.capabilities.previous {
+        tags.push(action_tag(ctl, "previous", &buttons.previous));
+    }
+    if data.capabilities.pause {
+        let label = match data.status {
+            Some(PlaybackStatus::Playing) => &buttons.pause,
+            _ => &buttons.play,
+        };
+        tags.push(action_tag(ctl, "play-pause", label));
+    }
+    if data.capabilities.next {
+        tags.push(action_tag(ctl, "next", &buttons.next));
+    }
+
+    if !tags.is_empty() {
+        b.append("  ");
+        b.append(tags.join(" "));
+    }
+}
+
 /// This function appends each field in the Data.field_text Hashmap to the given String builder.
 /// It does some formatting as well.
 ///
 /// Input:
 /// b: mutable String builder to append to.
 /// cfg: Config struct for the program.
-/// data: Data struct containing the field-text HashMap.
-fn append_fields(b: &mut Builder, cfg: &Config, data: &Data) {
+/// data: Data struct containing the state of the program.
+/// fields: the Fields to render.
+/// field_text: HashMap containing the (truncated) strings for each field.
+fn append_fields(
+    b: &mut Builder,
+    cfg: &Config,
+    data: &Data,
+    fields: &[Field],
+    field_text: &HashMap<String, String>,
+) {
     let mut idx = 0;
-    let len = data.field_text.len() as i32;
+    let len = fields
+        .iter()
+        .filter(|f| field_text.contains_key(&f.field))
+        .count();
 
-    for field in &cfg.metadata_fields {
-        if let Some(string) = data.field_text.get(&field.field) {
+    for field in fields {
+        if let Some(string) = field_text.get(&field.field) {
             idx += 1;
 
-            if cfg.escape_chars {
-                let s: &String = &string
-                    .chars()
-                    .map(|x| match x {
-                        '&' => "&amp;".to_owned(),
-                        _ => x.to_string(),
-                    })
-                    .collect();
-
-                b.append(field.format.replace("{}", s.as_str()));
-            } else {
-                b.append(field.format.replace("{}", string.as_str()));
-            }
+            b.append(format_field(cfg, data, field, string));
             if idx < len {
                 b.append(cfg.metadata_separator.as_str())
             };
@@ -113,21 +152,108 @@ fn append_fields(b: &mut Builder, cfg: &Config, data: &Data) {
     }
 }
 
+/// This function returns the value of a single template placeholder, truncated and escaped for output.
+///
+/// Input:
+/// cfg: Config struct for the program.
+/// data: Data struct containing the state of the program.
+/// field_text: HashMap containing the (truncated) strings for each field.
+/// p: the Placeholder to fill in.
+///
+/// Returns:
+/// the value, or None if the field has no (non-empty) value.
+fn placeholder_value(
+    cfg: &Config,
+    data: &Data,
+    field_text: &HashMap<String, String>,
+    p: &Placeholder,
+) -> Option<String> {
+    let mut value = match p.field.as_str() {
+        "prefix" => {
+            return match should_escape(cfg) {
+                true => Some(escape_markup(&data.prefix)),
+                false => Some(data.prefix.to_owned()),
+            }
+        }
+        "status" => format!("{:?}", data.status?),
+        key => field_text.get(key)?.to_owned(),
+    };
+    if value.is_empty() {
+        return None;
+    }
+    if let Some(num_chars) = p.num_chars {
+        // placeholders for a field in metadata_fields truncate the same way it does.
+        let mode = cfg
+            .active_fields(data.alt_layout)
+            .iter()
+            .find(|f| f.field == p.field)
+            .map_or(TruncateMode::default(), |f| f.truncate);
+        truncate(&mut value, num_chars, cfg.break_character.as_deref(), mode);
+    }
+    Some(sanitise(cfg, &value))
+}
+
+/// This function renders a list of template nodes.
+/// Placeholders without a value render their fallback, or nothing if they have none.
+/// Sections are left out entirely if any placeholder inside them (without fallback) has no value.
+///
+/// Input:
+/// cfg: Config struct for the program.
+/// data: Data struct containing the state of the program.
+/// field_text: HashMap containing the (truncated) strings for each field.
+/// nodes: the template nodes to render.
+///
+/// Returns:
+/// the rendered string, and whether every placeholder had a value.
+fn render_nodes(
+    cfg: &Config,
+    data: &Data,
+    field_text: &HashMap<String, String>,
+    nodes: &[Node],
+) -> (String, bool) {
+    let mut out = String::new();
+    let mut complete = true;
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Placeholder(p) => match (placeholder_value(cfg, data, field_text, p), &p.fallback) {
+                (Some(value), _) => out.push_str(&value),
+                (None, Some(fallback)) => out.push_str(fallback),
+                (None, None) => complete = false,
+            },
+            Node::Section(nodes) => {
+                let (section, section_complete) = render_nodes(cfg, data, field_text, nodes);
+                if section_complete {
+                    out.push_str(&section);
+                }
+            }
+        }
+    }
+    (out, complete)
+}
+
 /// This higher level function formats and appends the entire output to a string builder.
+/// If a format template is configured, it is rendered instead of the prefix and separated fields.
 ///
 /// Input:
 /// cfg: Config struct for the program.
 /// data: Data struct containing the state of the program.
+/// field_text: HashMap containing the (truncated) strings for each field.
 ///
 /// Returns:
 /// String to be outputted.
-fn build_string(cfg: &Config, data: &Data) -> String {
+fn build_string(cfg: &Config, data: &Data, field_text: &HashMap<String, String>) -> String {
     let mut b = Builder::default();
 
-    if cfg.render_prefix {
-        append_prefix(&mut b, data);
+    if let Some(template) = &cfg.template {
+        b.append(render_nodes(cfg, data, field_text, &template.nodes).0);
+    } else {
+        if cfg.render_prefix {
+            append_prefix(&mut b, cfg, data);
+        }
+        append_fields(&mut b, cfg, data, cfg.active_fields(data.alt_layout), field_text);
     }
-    append_fields(&mut b, cfg, data);
+    append_buttons(&mut b, cfg, data);
 
     b.string().unwrap_or_else(|e| {
         error!("{e}");
@@ -135,27 +261,495 @@ fn build_string(cfg: &Config, data: &Data) -> String {
     })
 }
 
+/// This function builds the blocks for i3bar output: one for the prefix (if rendered), and one for each available field.
+/// The prefix block is named "prefix"; field blocks are named "field" and use the field's name as instance.
+/// If a format template is configured, it is rendered as a single block named "format" instead.
+///
+/// Input:
+/// cfg: Config struct for the program.
+/// data: Data struct containing the state of the program.
+/// field_text: HashMap containing the (truncated) strings for each field.
+fn build_blocks(
+    cfg: &Config,
+    data: &Data,
+    field_text: &HashMap<String, String>,
+) -> Vec<I3barBlock> {
+    let markup = should_escape(cfg).then(|| "pango".to_owned());
+    let mut out = Vec::new();
+
+    if let Some(template) = &cfg.template {
+        out.push(I3barBlock {
+            full_text: render_nodes(cfg, data, field_text, &template.nodes).0,
+            name: "format".to_owned(),
+            instance: String::new(),
+            markup,
+        });
+        return out;
+    }
+
+    if cfg.render_prefix {
+        out.push(I3barBlock {
+            full_text: match should_escape(cfg) {
+                true => escape_markup(&data.prefix),
+                false => data.prefix.to_owned(),
+            },
+            name: "prefix".to_owned(),
+            instance: data.prefix.to_owned(),
+            markup: markup.clone(),
+        });
+    }
+    for field in cfg.active_fields(data.alt_layout) {
+        if let Some(string) = field_text.get(&field.field) {
+            out.push(I3barBlock {
+                full_text: format_field(cfg, data, field, string),
+                name: "field".to_owned(),
+                instance: field.field.to_owned(),
+                markup: markup.clone(),
+            });
+        }
+    }
+    out
+}
+
+/// This function builds one element of the infinite i3bar array.
+///
+/// Input:
+/// blocks: the blocks to show in the bar; empty to hide the output.
+fn build_i3bar(blocks: Vec<I3barBlock>) -> String {
+    match serde_json::to_string(&blocks) {
+        Ok(json) => format!("{json},"),
+        Err(e) => {
+            error!("{e}");
+            "[],".to_owned()
+        }
+    }
+}
+
+/// This function prints whatever has to precede the first line of output.
+/// This is only the case for i3bar output, which starts with a header and the opening of an infinite array.
+pub fn print_header(cfg: &Config) {
+    if cfg.output == Output::I3bar {
+        let header = I3barHeader {
+            version: 1,
+            click_events: true,
+        };
+        match serde_json::to_string(&header) {
+            Ok(json) => println!("{json}\n["),
+            Err(e) => error!("{e}"),
+        }
+    }
+}
+
+/// This function builds the tooltip for waybar output: every rendered field in full, one per line.
+///
+/// Input:
+/// cfg: Config struct for the program.
+/// data: Data struct containing the untruncated field strings.
+fn build_tooltip(cfg: &Config, data: &Data) -> String {
+    cfg.active_fields(data.alt_layout)
+        .iter()
+        .filter_map(|field| {
+            data.field_text
+                .get(&field.field)
+                .map(|s| field.format.replace("{}", &sanitise(cfg, s)))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// This function builds one line of waybar JSON around the given text.
+/// The class consists of the playback status and the identity of the active player, the percentage of the position within the track.
+///
+/// Input:
+/// cfg: Config struct for the program.
+/// data: Data struct containing the state of the program.
+/// text: the already built (and escaped) text to show in the bar.
+///
+/// Returns:
+/// String to be outputted.
+fn build_waybar(cfg: &Config, data: &Data, text: String) -> String {
+    let mut out = WaybarOutput {
+        text,
+        ..Default::default()
+    };
+    if let (Some(player), false) = (&data.current_player, out.text.is_empty()) {
+        out.tooltip = build_tooltip(cfg, data);
+        out.alt = escape_markup(&data.prefix);
+        if let Some(status) = data.status {
+            out.class.push(format!("{status:?}").to_lowercase());
+        }
+        out.class
+            .push(player.identity().to_lowercase().replace(' ', "-"));
+
+        // the position is interpolated from the last sample, so the player is not queried on every print.
+        out.percentage = data.position.as_ref().and_then(PositionSample::percentage);
+    }
+
+    serde_json::to_string(&out).unwrap_or_else(|e| {
+        error!("{e}");
+        "{\"text\":\"\"}".to_owned()
+    })
+}
+
+/// This function returns the field strings of a player, truncated or scrolled to their maximum length.
+///
+/// Input:
+/// cfg: Config struct in effect for the player.
+/// data: Data struct containing the state of the player.
+fn cut_field_text(cfg: &Config, data: &Data) -> HashMap<String, String> {
+    let mut field_text = data.field_text.clone();
+    cutoff(
+        cfg.active_fields(data.alt_layout),
+        cfg.break_character.as_deref(),
+        &data.scroll,
+        &mut field_text,
+    );
+    field_text
+}
+
+/// This function builds the text of the active player and, in multi-player mode, that of the other players.
+/// The active player comes first and is formatted with active_format; in compact mode only the number of other players is added.
+///
+/// Input:
+/// cfg: global Config struct for the program; each player uses the config in effect for it.
+/// data: Data struct containing the state of the program.
+///
+/// Returns:
+/// the text to show in the bar.
+fn build_text(cfg: &Config, data: &Data) -> String {
+    let active = data.config(cfg);
+    let text = build_string(active, data, &cut_field_text(active, data));
+    let Some(multi) = &cfg.multi_player else {
+        return text;
+    };
+    if multi.compact {
+        return match data.other_players.len() {
+            0 => text,
+            n => text + &multi.compact_format.replace("{}", &n.to_string()),
+        };
+    }
+
+    let mut parts = vec![multi.active_format.replace("{}", &text)];
+    for other in data.other_players.iter().filter(|o| !o.field_text.is_empty()) {
+        let other_cfg = other.config(cfg);
+        parts.push(build_string(other_cfg, other, &cut_field_text(other_cfg, other)));
+    }
+    parts.join(&multi.player_separator)
+}
+
+/// This function builds the i3bar blocks of the active player and, in multi-player mode, those of the other players.
+/// In compact mode a single block named "others" contains the number of other players.
+///
+/// Input:
+/// cfg: global Config struct for the program; each player uses the config in effect for it.
+/// data: Data struct containing the state of the program.
+fn build_all_blocks(cfg: &Config, data: &Data) -> Vec<I3barBlock> {
+    let active = data.config(cfg);
+    let mut out = build_blocks(active, data, &cut_field_text(active, data));
+    let Some(multi) = &cfg.multi_player else {
+        return out;
+    };
+    if multi.compact {
+        if !data.other_players.is_empty() {
+            out.push(I3barBlock {
+                full_text: multi
+                    .compact_format
+                    .replace("{}", &data.other_players.len().to_string()),
+                name: "others".to_owned(),
+                instance: String::new(),
+                markup: None,
+            });
+        }
+        return out;
+    }
+    for other in data.other_players.iter().filter(|o| !o.field_text.is_empty()) {
+        let other_cfg = other.config(cfg);
+        out.extend(build_blocks(other_cfg, other, &cut_field_text(other_cfg, other)));
+    }
+    out
+}
+
 /// This higher level function calls the appropriate string building function depending on a few settings:
-/// If either no metadata is specified in the config or no metadata is currently available => it prints an empty line.
-/// If no player is currently active and hide_output is true => it prints an empty line.
-/// Else => it builds and prints the appropriate output string.
+/// If either no metadata is specified in the config or no metadata is currently available => it builds an empty line.
+/// If no player is currently active and hide_output is true => it builds an empty line.
+/// Else => it builds the appropriate output string.
+/// For waybar and i3bar output, the line is wrapped in the appropriate JSON.
+///
+/// Input:
+/// cfg: global Config struct for the program; the config in effect for the active player is taken from Data.
+/// data: Data struct containing the state of the program.
+///
+/// Returns:
+/// the line to be outputted.
+fn build_line(cfg: &Config, data: &Data) -> String {
+    let active = data.config(cfg);
+    let hidden = (active.hide_output && data.current_player.is_none())
+        || data.field_text.is_empty()
+        || active.field_keys(data.alt_layout).is_empty();
+
+    match (active.output, hidden) {
+        (Output::Polybar, true) => String::new(),
+        (Output::Polybar, false) => build_text(cfg, data),
+        (Output::Waybar, true) => build_waybar(active, data, String::new()),
+        (Output::Waybar, false) => build_waybar(active, data, build_text(cfg, data)),
+        (Output::I3bar, true) => build_i3bar(Vec::new()),
+        (Output::I3bar, false) => build_i3bar(build_all_blocks(cfg, data)),
+    }
+}
+
+/// This function prints the output line for the current state to stdout.
+/// Nothing is printed if the line is identical to the previously printed one.
+/// The line is built using the config in effect for the active player (see Config::player_overrides).
 ///
 /// Input:
 /// cfg: Config struct for the program.
 /// data: mutable Data struct containing the state of the program.
 pub fn print_text(cfg: &Config, data: &mut Data) {
-    if (cfg.hide_output && data.current_player.is_none())
-        || data.field_text.is_empty()
-        || cfg.metadata_fields.is_empty()
-    {
-        println!("");
-    } else {
-        cutoff(
-            &cfg.metadata_fields,
-            cfg.break_character,
-            cfg.fuzzy,
-            &mut data.field_text,
+    let line = build_line(cfg, data);
+    if data.last_output.as_ref() != Some(&line) {
+        println!("{line}");
+        data.last_output = Some(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use mpris::{Metadata, MetadataValue, PlaybackStatus};
+    use serde_json::Value;
+
+    use super::build_line;
+    use crate::structs::{
+        config::{Buttons, Config, Field, MultiPlayer, Output, Overflow, PlayerOverride},
+        data::{Capabilities, Data},
+    };
+    use crate::update_message::update_fields;
+    use crate::update_scroll::update_scroll;
+
+    /// Runs the given title and artists through update_fields and build_line, as the main loop would.
+    fn render(cfg: &Config, title: &str, artists: &[&str]) -> String {
+        render_as(cfg, None, title, artists)
+    }
+
+    /// Like render, for the active player having the given identity, if any.
+    fn render_as(cfg: &Config, identity: Option<&str>, title: &str, artists: &[&str]) -> String {
+        let mut data = Data {
+            effective_config: identity.and_then(|i| cfg.with_override(i)).map(Rc::new),
+            ..Default::default()
+        };
+        let meta = Metadata::from(HashMap::from([
+            (
+                "xesam:title".to_owned(),
+                MetadataValue::String(title.to_owned()),
+            ),
+            (
+                "xesam:artist".to_owned(),
+                MetadataValue::Array(
+                    artists
+                        .iter()
+                        .map(|a| MetadataValue::String(a.to_string()))
+                        .collect(),
+                ),
+            ),
+        ]));
+        data.with_config(cfg, |active, data| {
+            update_fields(active, data, &meta, &active.build_rating_strings())
+        });
+        build_line(cfg, &data)
+    }
+
+    fn config(output: Output) -> Config {
+        Config {
+            output,
+            hide_output: false,
+            render_prefix: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn polybar_tags_in_title_are_escaped() {
+        let cfg = config(Output::Polybar);
+        assert_eq!(
+            render(&cfg, "%{A1:rm -rf ~:}click me%{A}", &["100% Artist"]),
+            "%%{A1:rm -rf ~:}click me%%{A} | 100%% Artist"
+        );
+    }
+
+    #[test]
+    fn line_breaks_and_tabs_become_spaces() {
+        let cfg = config(Output::Polybar);
+        let line = render(&cfg, "Line one\nLine two\r\n\tend", &["A\nB", "C"]);
+        assert_eq!(line, "Line one Line two   end | A B+C");
+        assert!(!line.contains(['\n', '\r', '\t']));
+    }
+
+    #[test]
+    fn other_control_characters_are_removed() {
+        let cfg = config(Output::Polybar);
+        assert_eq!(
+            render(&cfg, "bell\u{7}\u{1b}[31mred\u{0}", &["art\u{7f}ist"]),
+            "bell[31mred | artist"
+        );
+    }
+
+    #[test]
+    fn waybar_escapes_markup() {
+        let cfg = config(Output::Waybar);
+        let line = render(&cfg, "<b>\"Tom & Jerry's\"</b>", &["A\nB"]);
+        assert!(!line.contains('\n'));
+
+        let json: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            json["text"],
+            "&lt;b&gt;&quot;Tom &amp; Jerry&#39;s&quot;&lt;/b&gt; | A B"
+        );
+    }
+
+    #[test]
+    fn i3bar_blocks_stay_on_one_line() {
+        let cfg = config(Output::I3bar);
+        let line = render(&cfg, "%{F#f00}<i>title</i>\n", &["]},{\"full_text\":\"x"]);
+        assert!(!line.contains('\n'));
+
+        let json: Value = serde_json::from_str(line.trim_end_matches(',')).unwrap();
+        assert_eq!(json[0]["full_text"], "%{F#f00}<i>title</i> ");
+        assert_eq!(json[1]["full_text"], "]},{\"full_text\":\"x");
+        assert_eq!(json.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn i3bar_escapes_markup_when_enabled() {
+        let cfg = Config {
+            escape_chars: true,
+            ..config(Output::I3bar)
+        };
+        let line = render(&cfg, "Tom & Jerry", &["<artist>"]);
+
+        let json: Value = serde_json::from_str(line.trim_end_matches(',')).unwrap();
+        assert_eq!(json[0]["full_text"], "Tom &amp; Jerry");
+        assert_eq!(json[0]["markup"], "pango");
+        assert_eq!(json[1]["full_text"], "&lt;artist&gt;");
+    }
+
+    #[test]
+    fn template_sections_vanish_and_fallbacks_apply() {
+        let mut cfg = Config {
+            format: Some("{title:5}[ - {artist}][ ({album|Unknown})]".to_owned()),
+            ..config(Output::Polybar)
+        };
+        cfg.compile_template().unwrap();
+
+        assert_eq!(render(&cfg, "Hello World", &[]), "Hello- (Unknown)");
+        assert_eq!(render(&cfg, "Hi", &["Me"]), "Hi - Me (Unknown)");
+    }
+
+    #[test]
+    fn template_values_are_escaped_but_text_is_not() {
+        let mut cfg = Config {
+            format: Some("%{F#f00}{title}%{F-}".to_owned()),
+            ..config(Output::Polybar)
+        };
+        cfg.compile_template().unwrap();
+
+        assert_eq!(render(&cfg, "50%{x}", &[]), "%{F#f00}50%%{x}%{F-}");
+    }
+
+    #[test]
+    fn multi_player_joins_players() {
+        let mut cfg = config(Output::Polybar);
+        cfg.render_prefix = true;
+        cfg.multi_player = Some(MultiPlayer {
+            active_format: "[{}]".to_owned(),
+            ..Default::default()
+        });
+        let field_text = |title: &str| HashMap::from([("xesam:title".to_owned(), title.to_owned())]);
+        let mut data = Data {
+            prefix: "s".to_owned(),
+            field_text: field_text("Song"),
+            ..Default::default()
+        };
+        data.other_players.push(Data {
+            prefix: "f".to_owned(),
+            field_text: field_text("Video"),
+            ..Default::default()
+        });
+        // a player without metadata is left out, but still counted in compact mode.
+        data.other_players.push(Data {
+            prefix: "m".to_owned(),
+            ..Default::default()
+        });
+        assert_eq!(build_line(&cfg, &data), "[s  Song] // f  Video");
+
+        cfg.multi_player.as_mut().unwrap().compact = true;
+        assert_eq!(build_line(&cfg, &data), "s  Song (+2)");
+
+        // scrolling fields of the other players move along with those of the active player.
+        cfg.multi_player.as_mut().unwrap().compact = false;
+        cfg.metadata_fields = vec![Field::constructor("xesam:title", 4, None)];
+        cfg.metadata_fields[0].overflow = Overflow::Scroll;
+        data.field_text = field_text("Songs");
+        data.other_players[0].field_text = field_text("Videos");
+        assert_eq!(build_line(&cfg, &data), "[s  Song] // f  Vide");
+        update_scroll(&cfg, &mut data);
+        update_scroll(&cfg, &mut data);
+        assert_eq!(build_line(&cfg, &data), "[s  ngs ] // f  deos");
+    }
+
+    #[test]
+    fn player_overrides_apply_per_identity() {
+        let mut cfg = config(Output::Polybar);
+        cfg.player_overrides.insert(
+            "Spotify".to_owned(),
+            PlayerOverride {
+                format: Some("{xesam:artist}: {xesam:title}".to_owned()),
+                ..Default::default()
+            },
+        );
+        cfg.player_overrides.insert(
+            "mpv".to_owned(),
+            PlayerOverride {
+                metadata_fields: Some(vec![Field::constructor("xesam:title", 5, None)]),
+                ..Default::default()
+            },
+        );
+        cfg.compile_template().unwrap();
+
+        assert_eq!(render_as(&cfg, None, "Allegro", &["Vivaldi"]), "Allegro | Vivaldi");
+        assert_eq!(render_as(&cfg, Some("Spotify"), "Allegro", &["Vivaldi"]), "Vivaldi: Allegro");
+        assert_eq!(render_as(&cfg, Some("mpv"), "Allegro", &["Vivaldi"]), "Alleg-");
+        assert_eq!(render_as(&cfg, Some("VLC Media Player"), "Allegro", &["Vivaldi"]), "Allegro | Vivaldi");
+    }
+
+    #[test]
+    fn buttons_follow_cached_capabilities() {
+        let cfg = Config {
+            buttons: Some(Buttons {
+                previous: "<".to_owned(),
+                play: ">".to_owned(),
+                pause: "=".to_owned(),
+                next: ">>".to_owned(),
+            }),
+            ..config(Output::Polybar)
+        };
+        let mut data = Data {
+            field_text: HashMap::from([("xesam:title".to_owned(), "Song".to_owned())]),
+            ctl_command: Some("ctl".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(build_line(&cfg, &data), "Song");
+
+        data.capabilities = Capabilities {
+            previous: false,
+            pause: true,
+            next: true,
+        };
+        data.status = Some(PlaybackStatus::Playing);
+        assert_eq!(
+            build_line(&cfg, &data),
+            "Song  %{A1:ctl play-pause:}=%{A} %{A1:ctl next:}>>%{A}"
         );
-        println!("{}", build_string(cfg, data));
     }
 }
@@ -0,0 +1,172 @@
+//! This file deals with intercepting the signals configured in the signal_actions table.
+//! Signals are received on a separate thread and passed to the main loop as events, so their actions are performed straight away.
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use log::{debug, error, trace};
+use signal_hook::iterator::{Handle, Signals};
+
+use crate::structs::{action::Action, config::Config, event::Event};
+
+/// This struct pairs one intercepted signal with the Action it triggers.
+pub struct SignalAction {
+    /// Name of the signal, as written in the config.
+    pub name: String,
+    /// Number of the signal.
+    pub signal: i32,
+    /// Action to perform when the signal arrives.
+    pub action: Action,
+}
+
+/// This struct contains the intercepted signals, along with the handle of the thread receiving them.
+#[derive(Default)]
+pub struct SignalActions {
+    /// All successfully registered signals.
+    pub actions: Vec<SignalAction>,
+    /// Handle to stop receiving the signals; None if none are registered.
+    handle: Option<Handle>,
+}
+
+impl SignalActions {
+    /// This function returns the registered signal with the given number, if any.
+    pub fn get(&self, signal: i32) -> Option<&SignalAction> {
+        self.actions.iter().find(|s| s.signal == signal)
+    }
+}
+
+/// This function converts a signal name to its' number.
+/// Accepted are SIGUSR1, SIGUSR2 and SIGRTMIN+n (with or without the SIG prefix).
+///
+/// Input:
+/// name: name of the signal.
+///
+/// Returns:
+/// the signal number, or a description of why the name is invalid.
+pub fn parse_signal(name: &str) -> Result<i32, String> {
+    let name = name.trim().to_uppercase();
+    match name.trim_start_matches("SIG") {
+        "USR1" => Ok(signal_hook::consts::SIGUSR1),
+        "USR2" => Ok(signal_hook::consts::SIGUSR2),
+        "RTMIN" => Ok(libc::SIGRTMIN()),
+        rt => match rt.strip_prefix("RTMIN+").map(str::parse::<i32>) {
+            Some(Ok(n)) if n >= 0 && libc::SIGRTMIN() + n <= libc::SIGRTMAX() => {
+                Ok(libc::SIGRTMIN() + n)
+            }
+            Some(_) => Err(format!(
+                "{name} is out of range (SIGRTMIN+0 to SIGRTMIN+{})",
+                libc::SIGRTMAX() - libc::SIGRTMIN()
+            )),
+            None => Err(format!(
+                "unsupported signal {name} (use SIGUSR1, SIGUSR2 or SIGRTMIN+n)"
+            )),
+        },
+    }
+}
+
+/// This function intercepts every signal in the signal_actions table of the config, and spawns a thread passing them to the main loop.
+/// Entries with an invalid signal or action are logged and skipped.
+///
+/// Input:
+/// cfg: Config struct of the program, containing the signal_actions table.
+/// tx: Sender through which received signals are passed to the main loop.
+///
+/// Returns:
+/// the successfully registered signals.
+pub fn register_signals(cfg: &Config, tx: Sender<Event>) -> SignalActions {
+    let mut actions = Vec::new();
+    for (name, action) in &cfg.signal_actions {
+        let action = match action.parse::<Action>() {
+            Ok(action) => action,
+            Err(e) => {
+                error!("signal_actions: {name}: {e}");
+                continue;
+            }
+        };
+        let signal = match parse_signal(name) {
+            Ok(signal) => signal,
+            Err(e) => {
+                error!("signal_actions: {e}");
+                continue;
+            }
+        };
+        debug!("register_signals: {name} => {action:?}");
+        actions.push(SignalAction {
+            name: name.to_owned(),
+            signal,
+            action,
+        });
+    }
+    if actions.is_empty() {
+        return SignalActions::default();
+    }
+
+    let mut signals = match Signals::new(actions.iter().map(|s| s.signal)) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("signal_actions: {e}");
+            return SignalActions::default();
+        }
+    };
+    let handle = signals.handle();
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            trace!("register_signals: received signal {signal}");
+            if tx.send(Event::Signal(signal)).is_err() {
+                return;
+            }
+        }
+    });
+    SignalActions {
+        actions,
+        handle: Some(handle),
+    }
+}
+
+/// This function stops receiving the given signals, so they can be registered anew from a reloaded config.
+/// The signals themselves stay intercepted, so they do not suddenly terminate the process.
+///
+/// Input:
+/// signals: the signals to unregister.
+pub fn unregister_signals(signals: SignalActions) {
+    if let Some(handle) = signals.handle {
+        handle.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_signal;
+    use crate::structs::action::Action;
+
+    #[test]
+    fn signal_names_are_parsed() {
+        let rtmin = libc::SIGRTMIN();
+        let range = libc::SIGRTMAX() - rtmin;
+        let cases = [
+            ("SIGUSR1", Ok(libc::SIGUSR1)),
+            ("usr2", Ok(libc::SIGUSR2)),
+            (" SIGRTMIN ", Ok(rtmin)),
+            ("SIGRTMIN+0", Ok(rtmin)),
+            ("RTMIN+3", Ok(rtmin + 3)),
+            (&format!("SIGRTMIN+{range}"), Ok(rtmin + range)),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(parse_signal(name), expected, "{name}");
+        }
+        for bad in [&format!("SIGRTMIN+{}", range + 1), "SIGRTMIN+-1", "SIGRTMIN+x", "SIGTERM", "SIGKILL", "", "12"] {
+            assert!(parse_signal(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn actions_are_parsed() {
+        assert!(matches!("play-pause".parse::<Action>(), Ok(Action::PlayPause)));
+        assert!(matches!("cycle-player".parse::<Action>(), Ok(Action::CycleNext)));
+        assert!(matches!("seek -5".parse::<Action>(), Ok(Action::Seek { offset }) if offset == -5.0));
+        assert!(matches!("set-volume 0.5".parse::<Action>(), Ok(Action::SetVolume { volume }) if volume == 0.5));
+        assert!(matches!("pick-player vlc".parse::<Action>(), Ok(Action::PickPlayer { player }) if player == "vlc"));
+        for bad in ["jump", "", "seek", "seek soon", "next now"] {
+            assert!(bad.parse::<Action>().is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! This file deals with finding config files, and loading a config file along with the files it includes.
+//!
+//! A config file is named either by a path (anything containing a '/' or ending in `.toml`), or by a name which is looked up
+//! as `<name>.toml` in `$XDG_CONFIG_HOME/polybar-now-playing` and then in each `$XDG_CONFIG_DIRS/polybar-now-playing`.
+//! The `include` key of a config file lists files to load before it; keys in the including file win, tables are merged key by key.
+//! Keys which are not part of the config are ignored, but collected so they can be reported.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::value::Table;
+use toml::Value;
+
+use crate::structs::config::Config;
+
+/// Name of the directory config files are looked up in.
+pub const APP_NAME: &str = "polybar-now-playing";
+
+/// This function returns the directories names are looked up in, in order of preference.
+fn config_dirs() -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    match env::var_os("XDG_CONFIG_HOME").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => out.push(dir),
+        _ => {
+            if let Some(home) = env::var_os("HOME") {
+                out.push(PathBuf::from(home).join(".config"));
+            }
+        }
+    }
+    let dirs = env::var("XDG_CONFIG_DIRS").unwrap_or_default();
+    let dirs = match dirs.is_empty() {
+        true => "/etc/xdg",
+        false => &dirs,
+    };
+    out.extend(dirs.split(':').map(PathBuf::from).filter(|d| d.is_absolute()));
+    out.into_iter().map(|d| d.join(APP_NAME)).collect()
+}
+
+/// This function resolves the name of a config file to its' path.
+///
+/// Input:
+/// name: a path (absolute, or relative to dir), or a name to look up in the XDG config directories.
+/// dir: directory relative paths are resolved against; the working directory if None.
+///
+/// Returns:
+/// the path of the config file. For a name found in none of the directories this is where it would be in the user's config directory.
+pub fn config_path(name: &str, dir: Option<&Path>) -> Result<PathBuf, String> {
+    if name.contains('/') || name.ends_with(".toml") {
+        let path = PathBuf::from(name);
+        return Ok(match (path.is_absolute(), dir) {
+            (true, _) => path,
+            (false, Some(dir)) => dir.join(path),
+            (false, None) => env::current_dir().map_err(|e| e.to_string())?.join(path),
+        });
+    }
+    let file = format!("{name}.toml");
+    let dirs = config_dirs();
+    dirs.iter()
+        .map(|d| d.join(&file))
+        .find(|p| p.is_file())
+        .or_else(|| dirs.first().map(|d| d.join(&file)))
+        .ok_or_else(|| "cannot find the config directory: neither XDG_CONFIG_HOME nor HOME is set".to_owned())
+}
+
+/// This function merges one table into another; keys in over win, except for tables present in both, which are merged in turn.
+fn merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// This function reads a config file, merged on top of the files it includes.
+///
+/// Input:
+/// path: path of the config file.
+/// stack: files currently being read, used to detect include cycles.
+/// files: list of files read so far, in order of merging; path is added after the files it includes.
+///
+/// Returns:
+/// the merged table, without the include keys of the included files.
+fn read_table(path: &Path, stack: &mut Vec<PathBuf>, files: &mut Vec<PathBuf>) -> Result<Table, String> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    if stack.contains(&canonical) {
+        let chain: Vec<_> = stack.iter().chain([&canonical]).map(|p| p.display().to_string()).collect();
+        return Err(format!("include cycle: {}", chain.join(" -> ")));
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut table = match text.parse::<Value>() {
+        Ok(Value::Table(table)) => table,
+        Ok(_) => return Err(format!("{}: not a table", path.display())),
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+
+    let includes: Vec<String> = match table.remove("include") {
+        Some(value) => value
+            .try_into()
+            .map_err(|_| format!("{}: include: expected an array of strings", path.display()))?,
+        None => Vec::new(),
+    };
+    // only the include key of the file named on the command line is kept, as the other files are merged already.
+    if stack.is_empty() && !includes.is_empty() {
+        table.insert("include".to_owned(), Value::try_from(&includes).map_err(|e| e.to_string())?);
+    }
+    stack.push(canonical);
+    let mut merged = Table::new();
+    for include in &includes {
+        let include = config_path(include, path.parent())?;
+        merge(&mut merged, read_table(&include, stack, files)?);
+    }
+    stack.pop();
+
+    merge(&mut merged, table);
+    files.push(path.to_owned());
+    Ok(merged)
+}
+
+/// This function formats the path of an ignored key the same way the check subcommand reports locations, ie `metadata_fields[0].field`.
+fn key_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => format!("{}[{index}]", key_path(parent)),
+        serde_ignored::Path::Map { parent, key } => match key_path(parent) {
+            parent if parent.is_empty() => key.to_owned(),
+            parent => format!("{parent}.{key}"),
+        },
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => key_path(parent),
+    }
+}
+
+/// This function loads a config file along with every file it includes.
+/// Unknown keys are ignored, and stored in the unknown_keys field of the Config.
+///
+/// Input:
+/// path: path of the config file.
+///
+/// Returns:
+/// the merged Config, and every file it was read from, in order of merging (the given file last).
+pub fn load_chain(path: &Path) -> Result<(Config, Vec<PathBuf>), String> {
+    let mut files = Vec::new();
+    let table = read_table(path, &mut Vec::new(), &mut files)?;
+    let mut unknown_keys = Vec::new();
+    let mut cfg: Config = serde_ignored::deserialize(Value::Table(table), |path| unknown_keys.push(key_path(&path)))
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    cfg.unknown_keys = unknown_keys;
+    Ok((cfg, files))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{config_path, load_chain};
+
+    /// This function creates an empty directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pnp-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("bars")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_including_file() {
+        let dir = test_dir("paths");
+        assert_eq!(config_path("base.toml", Some(&dir)).unwrap(), dir.join("base.toml"));
+        assert_eq!(config_path("../b", Some(&dir)).unwrap(), dir.join("../b"));
+        assert_eq!(config_path("/etc/b.toml", Some(&dir)).unwrap(), PathBuf::from("/etc/b.toml"));
+        assert!(config_path("default", None).unwrap().ends_with("polybar-now-playing/default.toml"));
+    }
+
+    #[test]
+    fn includes_are_merged_in_order() {
+        let dir = test_dir("merge");
+        fs::write(
+            dir.join("base.toml"),
+            "update_delay = 100\nhide_output = false\n[player_prefixes]\nmpv = 'm'\ndefault = '>'\n",
+        )
+        .unwrap();
+        fs::write(dir.join("colours.toml"), "update_delay = 200\nmetadata_separator = ' / '\n").unwrap();
+        fs::write(
+            dir.join("bars/top.toml"),
+            "include = ['../base.toml', '../colours.toml']\nmetadata_separator = ' - '\n[player_prefixes]\nmpv = 'M'\n",
+        )
+        .unwrap();
+
+        let (cfg, files) = load_chain(&dir.join("bars/top.toml")).unwrap();
+        assert_eq!(
+            files,
+            vec![dir.join("bars/../base.toml"), dir.join("bars/../colours.toml"), dir.join("bars/top.toml")]
+        );
+        assert_eq!(cfg.update_delay, 200);
+        assert!(!cfg.hide_output);
+        assert_eq!(cfg.metadata_separator, " - ");
+        assert_eq!(cfg.player_prefixes["mpv"], "M");
+        assert_eq!(cfg.player_prefixes["default"], ">");
+        assert!(cfg.render_prefix);
+        assert_eq!(cfg.include, vec!["../base.toml", "../colours.toml"]);
+    }
+
+    #[test]
+    fn include_cycles_are_an_error() {
+        let dir = test_dir("cycle");
+        fs::write(dir.join("a.toml"), "include = ['b.toml']\n").unwrap();
+        fs::write(dir.join("b.toml"), "include = ['a.toml']\n").unwrap();
+        let e = load_chain(&dir.join("a.toml")).err().unwrap();
+        assert!(e.starts_with("include cycle"), "{e}");
+    }
+
+    #[test]
+    fn unknown_keys_are_collected() {
+        let dir = test_dir("unknown");
+        fs::write(
+            dir.join("base.toml"),
+            "[buttons]\nprevious = '<'\nplay = 'p'\npause = 'P'\nnext = '>'\nstop = 's'\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("top.toml"),
+            "include = ['base.toml']\nupdate_dealy = 10\n[[metadata_feilds]]\nfield = 'xesam:title'\n\
+             [[metadata_fields]]\nfield = 'xesam:title'\nnum_chars = 10\nformat = '{}'\nscroll_gapp = ' '\n",
+        )
+        .unwrap();
+
+        let (cfg, _) = load_chain(&dir.join("top.toml")).unwrap();
+        assert_eq!(
+            cfg.unknown_keys,
+            vec!["buttons.stop", "metadata_fields[0].scroll_gapp", "update_dealy", "metadata_feilds"]
+        );
+        assert_eq!(cfg.update_delay, 5000);
+    }
+}
@@ -0,0 +1,69 @@
+//! This file deals with making metadata safe to hand to the various bars.
+//! Metadata is controlled by whoever made the media file, so none of it can be trusted to be free of control characters or markup.
+use crate::structs::config::{Config, Output};
+
+/// This function replaces line breaks and tabs by spaces, and removes all other control characters.
+/// A line break would otherwise split the output over multiple lines, which every bar reads as multiple updates.
+///
+/// Input:
+/// str: string to clean up.
+///
+/// Returns:
+/// the string without control characters.
+pub fn strip_control(str: &str) -> String {
+    str.chars()
+        .filter_map(|c| match c {
+            '\n' | '\r' | '\t' | '\u{0b}' | '\u{0c}' | '\u{85}' | '\u{2028}' | '\u{2029}' => {
+                Some(' ')
+            }
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+/// This function escapes all characters with a special meaning in Pango markup.
+///
+/// Input:
+/// str: string to escape.
+///
+/// Returns:
+/// the escaped string.
+pub fn escape_markup(str: &str) -> String {
+    str.chars()
+        .map(|x| match x {
+            '&' => "&amp;".to_owned(),
+            '<' => "&lt;".to_owned(),
+            '>' => "&gt;".to_owned(),
+            '"' => "&quot;".to_owned(),
+            '\'' => "&#39;".to_owned(),
+            _ => x.to_string(),
+        })
+        .collect()
+}
+
+/// This function returns whether strings should be escaped as Pango markup before being output.
+pub fn should_escape(cfg: &Config) -> bool {
+    cfg.escape_chars || cfg.output == Output::Waybar
+}
+
+/// This function escapes a metadata string for the configured output.
+/// Polybar output has every '%' doubled, so a title containing "%{" cannot be read as a formatting tag.
+/// Pango markup is escaped if the output is waybar or escape_chars is enabled.
+///
+/// Input:
+/// cfg: Config struct for the program.
+/// str: metadata string to escape; should already be free of control characters.
+///
+/// Returns:
+/// the escaped string.
+pub fn sanitise(cfg: &Config, str: &str) -> String {
+    let str = match should_escape(cfg) {
+        true => escape_markup(str),
+        false => str.to_owned(),
+    };
+    match cfg.output {
+        Output::Polybar => str.replace('%', "%%"),
+        _ => str,
+    }
+}
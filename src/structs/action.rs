@@ -0,0 +1,116 @@
+//! This file contains the actions that can be performed on the active player at run-time, as well as the reply to them.
+use std::str::FromStr;
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+/// This enum represents one command sent to a running instance of the program.
+/// It doubles as the `ctl` subcommand of the cli and as the wire format of the control socket.
+#[derive(clap::Subcommand, Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Action {
+    /// Play or pause the active player.
+    PlayPause,
+    /// Skip to the next track.
+    Next,
+    /// Go back to the previous track.
+    Previous,
+    /// Stop playback.
+    Stop,
+    /// Bring the active player's window to the front.
+    Raise,
+    /// Seek relative to the current position.
+    Seek {
+        /// Offset in seconds; negative values seek backwards.
+        #[arg(allow_negative_numbers = true)]
+        offset: f64,
+    },
+    /// Set the volume of the active player.
+    SetVolume {
+        /// New volume, where 0.0 is muted and 1.0 is 100%.
+        volume: f64,
+    },
+    /// Make the given player the active one, regardless of priorities.
+    ///
+    /// The player is pinned, ie kept until it disappears or unpin is sent.
+    PickPlayer {
+        /// Mpris identity or bus name of the player to pick.
+        player: String,
+    },
+    /// Switch to the next available player, in order of priority.
+    ///
+    /// The chosen player is pinned, ie kept until it disappears or unpin is sent.
+    #[command(alias = "cycle-player")]
+    #[serde(alias = "cycle-player")]
+    CycleNext,
+    /// Switch to the previous available player, in order of priority.
+    ///
+    /// The chosen player is pinned, ie kept until it disappears or unpin is sent.
+    CyclePrevious,
+    /// Keep the active player, regardless of priorities, until it disappears or unpin is sent.
+    PinCurrent,
+    /// Go back to choosing the active player automatically.
+    Unpin,
+    /// Switch between the metadata_fields and alt_metadata_fields layouts.
+    ToggleLayout,
+    /// Raise the volume of the active player by volume_step.
+    VolumeUp,
+    /// Lower the volume of the active player by volume_step.
+    VolumeDown,
+    /// Mute the active player, or restore the volume it had before it was muted.
+    MuteToggle,
+    /// Turn shuffle of the active player on or off.
+    ToggleShuffle,
+    /// Switch the loop status of the active player from none to playlist to track, and back to none.
+    CycleLoop,
+}
+
+/// Wrapper used to parse an Action from a single string (as used in the config file), reusing the cli definitions.
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct ActionParser {
+    #[command(subcommand)]
+    action: Action,
+}
+
+/// Actions are written in the config file exactly as they would be on the command line, ie "next" or "seek -5".
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ActionParser::try_parse_from(s.split_whitespace())
+            .map(|p| p.action)
+            .map_err(|e| {
+                let msg = e.to_string();
+                let msg = msg.split("\n\nUsage").next().unwrap_or_default();
+                msg.split_whitespace().collect::<Vec<_>>().join(" ")
+            })
+    }
+}
+
+/// This struct is sent back over the control socket after an Action has been handled.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reply {
+    /// Whether the action was performed successfully.
+    pub ok: bool,
+    /// Human readable explanation of what happened.
+    pub message: String,
+}
+
+impl Reply {
+    /// Create a reply for a successfully performed action.
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            message: message.into(),
+        }
+    }
+
+    /// Create a reply for an action that could not be performed.
+    pub fn err(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            message: message.into(),
+        }
+    }
+}
@@ -1,9 +1,13 @@
 //! This file contains structs and functionality that are relevant to the Command Line Interface part of the program.
 use std::ffi::OsString;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use super::action::Action;
+use super::config::Output;
 
 /// Custom enum to define the desired loglevel during run-time.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(clap::ValueEnum, Clone)]
 pub enum LogLevel {
   TRACE,
@@ -14,6 +18,7 @@ pub enum LogLevel {
 }
 
 /// Implement Into<OsString> for LogLevel, so it can actually be used by env_logger.
+#[allow(clippy::from_over_into)]
 impl Into<OsString> for LogLevel {
   fn into(self) -> OsString {
     match self {
@@ -32,17 +37,66 @@ impl Into<OsString> for LogLevel {
 /// As such, most configuration is done through config files.
 #[derive(Parser)]
 pub struct Cli { 
-  /// The name of the config file to use. 
+  /// The config file to use.
+  ///
+  /// Either a path (containing a '/' or ending in .toml), or a name looked up as <name>.toml in $XDG_CONFIG_HOME/polybar-now-playing
+  /// and then in $XDG_CONFIG_DIRS/polybar-now-playing.
   #[arg(short = 'c', long = "config", default_value = "default")]
   pub config_file: String,
   /// Enable list mode.
   /// 
-  /// This mode prints all active players to stdout, to allow one to find the appropriate player names to use in the config files.
+  /// This mode prints all active players to stdout, along with the player_priorities and player_prefixes rules they match,
+  /// to allow one to find the appropriate player names to use in the config files.
   #[arg(short = 'l', long = "list")]
   pub list: bool,
   /// Set log level.
   /// 
   /// Sets the log level to print to stdout.
-  #[arg(long = "log", value_enum, default_value = "error")]
-  pub log_level: LogLevel
+  #[arg(long = "log", value_enum, default_value = "error", global = true)]
+  pub log_level: LogLevel,
+  /// Name of this instance.
+  ///
+  /// The control socket of the instance is named after this. Defaults to the process id.
+  /// When sending commands, this may be omitted if only one instance is running.
+  #[arg(short = 'i', long = "instance", global = true)]
+  pub instance: Option<String>,
+  /// Set output format.
+  ///
+  /// Overrides the output key of the config file.
+  #[arg(short = 'o', long = "output", value_enum)]
+  pub output: Option<Output>,
+  /// Override a key of the config file.
+  ///
+  /// The key is a path into the config, ie update_delay or metadata_fields[0].num_chars; the value is written as in the config file.
+  /// May be given more than once. Overrides from PNP_ environment variables are applied first.
+  /// Variable names are lowercased, so keys containing uppercase letters (ie player_priorities."VLC Media Player") can only be set here.
+  #[arg(long = "set", value_name = "KEY=VALUE")]
+  pub set: Vec<String>,
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+/// Subcommands which do something other than running the program in the bar.
+#[derive(Subcommand)]
+pub enum Command {
+  /// Send a command to a running instance.
+  ///
+  /// The reply of the instance is printed to stdout as JSON; the exit code is non-zero if the command failed.
+  Ctl {
+    #[command(subcommand)]
+    action: Action,
+  },
+  /// Check the config file for mistakes.
+  ///
+  /// Every problem is printed along with the key it was found at; the exit code is non-zero if there are any errors.
+  Check,
+  /// Upgrade the config file to the current schema.
+  ///
+  /// Renamed keys and actions are rewritten, unknown keys are dropped and missing keys are added with their default value.
+  /// The original file is kept as a backup, and every change is printed.
+  Migrate,
+  /// Print the config files in use.
+  ///
+  /// These are the file named by --config and every file it includes, in the order they are merged (the last one wins).
+  ConfigPath,
 }
\ No newline at end of file
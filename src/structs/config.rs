@@ -1,10 +1,83 @@
 //! This file contains structs and functions concerning themselves with the configuration of the program.
+use indexmap::IndexMap;
+use mpris::{LoopStatus, PlaybackStatus};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use super::player_rule::{PlayerInfo, PlayerRule};
+use super::template::Template;
+
+/// This enum describes what to do with a field whose output exceeds its' maximum length.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Cut the string off at the maximum length.
+    #[default]
+    Truncate,
+    /// Scroll the string through a window of the maximum length, one character per scroll_step.
+    Scroll,
+}
+
+/// This enum describes which part of a truncated string is kept.
+/// The break_character is inserted where the string was cut.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum TruncateMode {
+    /// Keep the start of the string.
+    #[default]
+    End,
+    /// Keep the end of the string.
+    Start,
+    /// Keep the start and the end of the string, cutting out the middle.
+    Middle,
+    /// Keep the start of the string, cut at the last whitespace which fits (or at the maximum length for a single word).
+    Word,
+}
+
+/// This enum describes how the active player is chosen from the acceptable ones.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionPolicy {
+    /// Playing and paused players before stopped ones, then by priority.
+    #[default]
+    Strict,
+    /// Playing players before paused ones, paused before stopped ones, then by priority.
+    PlayingBeforePaused,
+    /// Playing players before paused and stopped ones, then the player which most recently started playing, then by priority.
+    MostRecent,
+    /// As strict, but the active player is kept until it stops or disappears.
+    Sticky,
+}
+
+/// This enum describes how to order players with the same priority (and playback tier).
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum TieBreaker {
+    /// The player which started playing most recently comes first.
+    MostRecentlyPlaying,
+    /// Players are ordered by bus name.
+    BusName,
+    /// The player which was seen first comes first, so the choice never changes while both players exist.
+    #[default]
+    FirstSeen,
+}
+
+/// This enum describes the format in which output is written to stdout.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Output {
+    /// One plain line of text per update, as used by polybar's custom/script module.
+    #[default]
+    Polybar,
+    /// One line of JSON per update, as used by waybar's custom module (with return-type = json).
+    Waybar,
+    /// The i3bar protocol (also spoken by swaybar), with the prefix and each field as a separate block.
+    I3bar,
+}
+
 /// This struct represents one metadata field to be rendered, as well as the maximum length of its' output.
 /// There is also support for custom formatting.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Field {
     /// The name of the metadata field.
     pub field: String,
@@ -12,6 +85,22 @@ pub struct Field {
     pub num_chars: u32,
     /// Formatting to apply. (the value "{}" is substituted with the actual string)
     pub format: String,
+    /// What to do when the output exceeds num_chars.
+    #[serde(default)]
+    pub overflow: Overflow,
+    /// Which part of the output to keep when it is truncated.
+    #[serde(default)]
+    pub truncate: TruncateMode,
+    /// String inserted between the end and the start of a scrolling string.
+    #[serde(default = "Field::default_scroll_gap")]
+    pub scroll_gap: String,
+    /// Number of scroll steps to wait whenever a scrolling string is back at its' start.
+    #[serde(default)]
+    pub scroll_pause: u32,
+    /// Action to perform when the field is left-clicked (polybar output only).
+    /// Written the same as for the ctl subcommand, ie "next" or "seek -5".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub click: Option<String>,
 }
 
 impl Field {
@@ -28,9 +117,19 @@ impl Field {
             field,
             num_chars,
             format,
+            overflow: Overflow::default(),
+            truncate: TruncateMode::default(),
+            scroll_gap: Field::default_scroll_gap(),
+            scroll_pause: 0,
+            click: None,
         }
     }
 
+    /// This function returns the default gap between the end and start of a scrolling string.
+    fn default_scroll_gap() -> String {
+        "   ".to_owned()
+    }
+
     /// Create a new field from given values.
     /// input:
     /// field: name of the field
@@ -49,7 +148,7 @@ impl Field {
 }
 
 /// This struct contains the 3 symbols used to represent a given userRating in a media field.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Rating {
     /// character for an empty token
     pub nil: char,
@@ -136,6 +235,192 @@ impl Rating {
     }
 }
 
+/// This struct contains the symbols used to draw the time:progress and player:volume_bar fields, as well as their width.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProgressBar {
+    /// character for an empty token
+    pub nil: char,
+    /// character for a half token
+    pub half: char,
+    /// character for a full token
+    pub full: char,
+    /// number of tokens in the bar
+    pub width: u8,
+}
+
+impl ProgressBar {
+    /// This function draws the bar for the given fraction (ie the played part of the track).
+    ///
+    /// input:
+    /// fraction: filled part of the bar (0.0 <= fraction <= 1.0; other values are clamped).
+    ///
+    /// returns:
+    /// String of `width` tokens.
+    pub fn build_string(&self, fraction: f64) -> String {
+        let width = self.width as usize;
+        let halves = ((fraction.clamp(0.0, 1.0) * (width * 2) as f64).round()) as usize;
+        let mut out = self.full.to_string().repeat(halves / 2);
+        if halves % 2 == 1 {
+            out.push(self.half);
+        }
+        out.push_str(&self.nil.to_string().repeat(width - halves.div_ceil(2)));
+        out
+    }
+}
+
+/// Defaults for ProgressBar struct.
+/// uses UTF-8, ASCII compatible tokens.
+impl Default for ProgressBar {
+    fn default() -> Self {
+        Self {
+            nil: '-',
+            half: '=',
+            full: '#',
+            width: 10,
+        }
+    }
+}
+
+/// This struct contains the labels of the clickable playback buttons rendered in polybar output.
+/// Each button is only shown when the active player supports it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Buttons {
+    /// label of the previous track button
+    pub previous: String,
+    /// label of the play/pause button while the player is paused or stopped
+    pub play: String,
+    /// label of the play/pause button while the player is playing
+    pub pause: String,
+    /// label of the next track button
+    pub next: String,
+}
+
+/// This struct contains the icons used to represent the playback status in the player:status field.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StatusIcons {
+    /// icon shown while the player is playing
+    pub playing: String,
+    /// icon shown while the player is paused
+    pub paused: String,
+    /// icon shown while the player is stopped
+    pub stopped: String,
+}
+
+impl StatusIcons {
+    /// This function returns the icon for the given playback status.
+    pub fn icon(&self, status: PlaybackStatus) -> &str {
+        match status {
+            PlaybackStatus::Playing => &self.playing,
+            PlaybackStatus::Paused => &self.paused,
+            PlaybackStatus::Stopped => &self.stopped,
+        }
+    }
+}
+
+/// Defaults for StatusIcons struct.
+/// uses UTF-8, ASCII compatible tokens.
+impl Default for StatusIcons {
+    fn default() -> Self {
+        Self {
+            playing: ">".to_owned(),
+            paused: "||".to_owned(),
+            stopped: "[]".to_owned(),
+        }
+    }
+}
+
+/// This struct contains the icons used to represent the shuffle state in the player:shuffle field.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ShuffleIcons {
+    /// icon shown while shuffle is enabled
+    pub on: String,
+    /// icon shown while shuffle is disabled
+    pub off: String,
+}
+
+impl ShuffleIcons {
+    /// This function returns the icon for the given shuffle state.
+    pub fn icon(&self, shuffle: bool) -> &str {
+        match shuffle {
+            true => &self.on,
+            false => &self.off,
+        }
+    }
+}
+
+/// Defaults for ShuffleIcons struct.
+/// uses UTF-8, ASCII compatible tokens.
+impl Default for ShuffleIcons {
+    fn default() -> Self {
+        Self {
+            on: "S".to_owned(),
+            off: "-".to_owned(),
+        }
+    }
+}
+
+/// This struct contains the icons used to represent the loop status in the player:loop field.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LoopIcons {
+    /// icon shown while playback stops after the last track
+    pub none: String,
+    /// icon shown while the current track is repeated
+    pub track: String,
+    /// icon shown while the playlist is repeated
+    pub playlist: String,
+}
+
+impl LoopIcons {
+    /// This function returns the icon for the given loop status.
+    pub fn icon(&self, status: LoopStatus) -> &str {
+        match status {
+            LoopStatus::None => &self.none,
+            LoopStatus::Track => &self.track,
+            LoopStatus::Playlist => &self.playlist,
+        }
+    }
+}
+
+/// Defaults for LoopIcons struct.
+/// uses UTF-8, ASCII compatible tokens.
+impl Default for LoopIcons {
+    fn default() -> Self {
+        Self {
+            none: "-".to_owned(),
+            track: "T".to_owned(),
+            playlist: "P".to_owned(),
+        }
+    }
+}
+
+/// This struct contains the settings of multi-player mode, in which every acceptable player is rendered instead of only the active one.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MultiPlayer {
+    /// maximum number of players to render, including the active one
+    pub max_players: u8,
+    /// string to insert between players
+    pub player_separator: String,
+    /// format applied to the output of the active player, containing '{}'
+    pub active_format: String,
+    /// whether to render only the active player, followed by the number of other players
+    pub compact: bool,
+    /// format of the number of other players in compact mode, containing '{}'
+    pub compact_format: String,
+}
+
+/// Defaults for MultiPlayer struct.
+impl Default for MultiPlayer {
+    fn default() -> Self {
+        Self {
+            max_players: 3,
+            player_separator: " // ".to_owned(),
+            active_format: "{}".to_owned(),
+            compact: false,
+            compact_format: " (+{})".to_owned(),
+        }
+    }
+}
+
 /// Defaults for Rating struct.
 /// uses UTF-8, ASCII compatible tokens.
 impl Default for Rating {
@@ -148,39 +433,177 @@ impl Default for Rating {
     }
 }
 
+/// This struct contains the settings overridden for a single player, keyed by its' identity in Config::player_overrides.
+/// Every setting left out is taken from the global config.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PlayerOverride {
+    /// Replaces Config::metadata_fields.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_fields: Option<Vec<Field>>,
+    /// Replaces Config::metadata_separator.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_separator: Option<String>,
+    /// Replaces Config::break_character.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub break_character: Option<String>,
+    /// Replaces Config::rating_icons.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating_icons: Option<Rating>,
+    /// Replaces Config::format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// The parsed format template. Built by Config::compile_template during initialisation.
+    #[serde(skip)]
+    pub template: Option<Template>,
+}
+
+/// This struct contains the config in effect for one player: the global config with its' overrides applied.
+pub struct EffectiveConfig {
+    /// Identity of the player this config applies to.
+    pub identity: String,
+    /// The global config, with the overridden settings replaced.
+    pub cfg: Config,
+    /// Pre-computed rating strings for the overridden rating_icons.
+    pub ratings: Vec<String>,
+}
+
+/// Version of the config schema written by this version of the program.
+/// Bump this (and add a step to migrate_config.rs) whenever a key is renamed or removed.
+pub const CONFIG_VERSION: u32 = 3;
+
 /// This struct contains all possible configuration fields.
 /// It should not be used as mutable; all data in this struct should effectively be treated as read-only.
-#[derive(Serialize, Deserialize)]
+/// Keys missing from the config file take their value from Config::default, so files written by older versions still load.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Config {
+    /// Version of the schema the config file was written for. Files without it predate versioning (version 1).
+    #[serde(default = "Config::legacy_config_version")]
+    pub config_version: u32,
+    /// Config files to load before this one, see resolve_config.rs. Keys in this file win over those in the included files.
+    /// Paths are relative to this file; names are looked up the same as the --config argument.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Format in which to write the output. Can be overridden on the command line.
+    #[serde(default)]
+    pub output: Output,
     /// Whether to hide the last output if there are currently no accepted players.
     pub hide_output: bool,
-    /// Whether to apply 'fuzzy' cutoff to strings exceeding their maximum lenght.
-    pub fuzzy: bool,
     /// Whether to render the prefix at all.
     pub render_prefix: bool,
-    /// Time in milliseconds to wait between loops of the program.
+    /// Time in milliseconds between fallback polls of all players.
+    /// Updates are normally driven by D-Bus signals; this only catches anything those miss.
     pub update_delay: u64,
+    /// Time in milliseconds between scrolling fields moving one character.
+    #[serde(default = "Config::default_scroll_step")]
+    pub scroll_step: u64,
+    /// Template for the whole output line, ie "{prefix}  {title}[ - {artist}]".
+    /// If set, this replaces metadata_separator and the format of each Field; metadata_fields then only configure truncation and scrolling.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// The parsed format template. Built by compile_template during initialisation.
+    #[serde(skip)]
+    pub template: Option<Template>,
     /// String to insert between different metadata fields.
     pub metadata_separator: String,
     /// Character to insert between Array values (used when a MetadataVaue is of type Vec (ie multiple artists on one track)).
     pub array_separator: char,
-    /// Character to insert when a string is truncated. None implies no cut off character is inserted and the strings are truncated as is.
-    pub break_character: Option<char>,
-    /// Hashmap of mpris identities, describing what players are considered acceptable.
-    pub player_priorities: HashMap<String, u8>,
+    /// String to insert where a string is truncated, ie '…'. None implies nothing is inserted and the strings are truncated as is.
+    /// An absent key means None, rather than the default string.
+    #[serde(default)]
+    pub break_character: Option<String>,
+    /// Map of player rules (see player_rule.rs), describing what players are considered acceptable.
+    /// Rules are tried in the order they are written in; the first match wins.
+    pub player_priorities: IndexMap<String, u8>,
+    /// Whether players matching no rule in player_priorities are considered acceptable.
+    #[serde(default = "Config::default_accept_unknown_players")]
+    pub accept_unknown_players: bool,
+    /// Priority of players matching no rule in player_priorities, if accept_unknown_players is set.
+    #[serde(default = "Config::default_unknown_player_priority")]
+    pub unknown_player_priority: u8,
+    /// How to choose the active player from the acceptable ones.
+    #[serde(default)]
+    pub selection_policy: SelectionPolicy,
+    /// How to order players with equal priority.
+    #[serde(default)]
+    pub tie_breaker: TieBreaker,
+    /// The keys of player_priorities, parsed. Built by compile_rules during initialisation.
+    #[serde(skip)]
+    pub priority_rules: Vec<(PlayerRule, u8)>,
     /// Characters to use for the xesam:userRating field.
     /// If None, default values are used ('-', '/', '+').
     pub rating_icons: Option<Rating>,
+    /// Characters and width used for the time:progress field.
+    /// If None, default values are used ('-', '=', '#', 10 wide).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress_icons: Option<ProgressBar>,
+    /// Characters and width used for the player:volume_bar field.
+    /// If None, default values are used ('-', '=', '#', 10 wide).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume_icons: Option<ProgressBar>,
+    /// Icons used for the player:status field.
+    /// If None, default values are used ('>', '||', '[]').
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_icons: Option<StatusIcons>,
+    /// Icons used for the player:shuffle field.
+    /// If None, default values are used ('S', '-').
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shuffle_icons: Option<ShuffleIcons>,
+    /// Icons used for the player:loop field.
+    /// If None, default values are used ('-', 'T', 'P').
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_icons: Option<LoopIcons>,
+    /// Icon used for the player:pinned field while a player is pinned; the field is empty otherwise.
+    #[serde(default = "Config::default_pin_icon")]
+    pub pin_icon: String,
+    /// Amount by which the volume-up and volume-down actions change the volume (1.0 is 100%).
+    #[serde(default = "Config::default_volume_step")]
+    pub volume_step: f64,
+    /// Format of the time:position, time:remaining and time:length fields for tracks shorter than an hour.
+    /// {m} and {s} are replaced by minutes and seconds, {mm} and {ss} by the same padded to two digits.
+    #[serde(default = "Config::default_time_format")]
+    pub time_format: String,
+    /// Format of the time fields for tracks of an hour or longer. {h} is replaced by hours, otherwise as time_format.
+    #[serde(default = "Config::default_time_format_long")]
+    pub time_format_long: String,
     /// Vec of Fields. Each field represents one metadata_string to be shown in output, as well as the maximum number of characters for this field.
     /// Output is shown based on Vec index (vec\[0\] first, vec\[1\] second, etc).
     pub metadata_fields: Vec<Field>,
-    /// Hashmap which maps Player Identities (strings; key) to prefixes (char; value).
-    /// If left blank all players will use the default prefix character ('>').
-    pub player_prefixes: HashMap<String, String>,
+    /// Alternative Vec of Fields, which replaces metadata_fields while the toggle-layout action is active.
+    /// If left empty, toggle-layout does nothing.
+    #[serde(default)]
+    pub alt_metadata_fields: Vec<Field>,
+    /// Map of player rules (see player_rule.rs; key) to prefixes (value), tried in the order they are written in.
+    /// The "default" key is used for players no rule matches. If left blank all players will use the default prefix character ('>').
+    pub player_prefixes: IndexMap<String, String>,
+    /// The keys of player_prefixes (except "default"), parsed. Built by compile_rules during initialisation.
+    #[serde(skip)]
+    pub prefix_rules: Vec<(PlayerRule, String)>,
     /// Boolean which tells the program to escape special characters or not.
-    /// This is useful for some bar implementations (i.e. waybar needs to escape the '&' character).
-    /// Currently only escapes '&', i will be adding more as i run into them.
+    /// This escapes the characters Pango markup cares about ('&', '<', '>', '"' and '\'').
+    /// Waybar output is always escaped, regardless of this setting.
     pub escape_chars: bool,
+    /// Hashmap which maps signal names (SIGUSR1, SIGUSR2 or SIGRTMIN+n; key) to actions (value).
+    /// Actions are written the same as for the ctl subcommand, ie "next" or "seek -5".
+    #[serde(default = "Config::default_signal_actions")]
+    pub signal_actions: HashMap<String, String>,
+    /// Labels of the clickable previous, play/pause and next buttons appended to polybar output.
+    /// If None, no buttons are rendered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buttons: Option<Buttons>,
+    /// Settings of multi-player mode. If None, only the active player is rendered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multi_player: Option<MultiPlayer>,
+    /// Hashmap which maps mouse buttons (1 = left, 2 = middle, 3 = right, 4/5 = scroll up/down; key) to actions (value).
+    /// Used for the click events of i3bar output.
+    #[serde(default = "Config::default_click_actions")]
+    pub click_actions: HashMap<String, String>,
+    /// Hashmap which maps Player Identities (key) to settings which replace the global ones while that player is active (value).
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub player_overrides: HashMap<String, PlayerOverride>,
+    /// Paths of the keys in the config files which are not part of the config, and were ignored. Filled in by load_chain.
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
 }
 
 /// Defaults for the Config struct.
@@ -188,32 +611,189 @@ pub struct Config {
 impl Default for Config {
     fn default() -> Self {
         Config {
+            config_version: CONFIG_VERSION,
+            include: Vec::new(),
+            output: Output::default(),
             hide_output: true,
-            fuzzy: false,
             render_prefix: true,
-            update_delay: 300_u64,
+            update_delay: 5000_u64,
+            scroll_step: Config::default_scroll_step(),
+            format: None,
+            template: None,
             metadata_separator: " | ".to_owned(),
             array_separator: '+',
-            break_character: Some('-'),
+            break_character: Some("-".to_owned()),
             player_priorities: Config::default_player_priorities(),
+            priority_rules: Vec::new(),
+            accept_unknown_players: Config::default_accept_unknown_players(),
+            unknown_player_priority: Config::default_unknown_player_priority(),
+            selection_policy: SelectionPolicy::default(),
+            tie_breaker: TieBreaker::default(),
             rating_icons: Some(Rating::default()),
+            progress_icons: None,
+            volume_icons: None,
+            status_icons: None,
+            shuffle_icons: None,
+            loop_icons: None,
+            pin_icon: Config::default_pin_icon(),
+            volume_step: Config::default_volume_step(),
+            time_format: Config::default_time_format(),
+            time_format_long: Config::default_time_format_long(),
             metadata_fields: Config::default_metadata_fields(),
             player_prefixes: Config::default_player_prefixes(),
+            prefix_rules: Vec::new(),
             escape_chars: false,
+            alt_metadata_fields: Vec::new(),
+            signal_actions: Config::default_signal_actions(),
+            click_actions: Config::default_click_actions(),
+            buttons: None,
+            multi_player: None,
+            player_overrides: HashMap::new(),
+            unknown_keys: Vec::new(),
         }
     }
 }
 
 impl Config {
-    /// This function returns the index of a given player identity in the player_priorities hashmap.
-    /// If the given identity is not in the map, the value of i32::MAX is returned instead.
-    pub fn find_player_priorities_idx(&self, name: &str) -> u8 {
-        match self.player_priorities.get(name) {
-            Some(val) => *val,
-            None => u8::MAX,
+    /// This function returns the first player_priorities rule matching the given player, along with its' priority.
+    pub fn match_priority(&self, info: &PlayerInfo) -> Option<(&PlayerRule, u8)> {
+        self.priority_rules
+            .iter()
+            .find(|(rule, _)| rule.matches(info))
+            .map(|(rule, idx)| (rule, *idx))
+    }
+
+    /// This function returns the priority of the given player.
+    /// If no rule in player_priorities matches the player, unknown_player_priority is returned if such players are accepted, None otherwise.
+    pub fn find_player_priorities_idx(&self, info: &PlayerInfo) -> Option<u8> {
+        match self.match_priority(info) {
+            Some((_, idx)) => Some(idx),
+            None if self.accept_unknown_players => Some(self.unknown_player_priority),
+            None => None,
         }
     }
 
+    /// This function returns the first player_prefixes rule matching the given player, along with its' prefix.
+    pub fn match_prefix(&self, info: &PlayerInfo) -> Option<(&PlayerRule, &str)> {
+        self.prefix_rules
+            .iter()
+            .find(|(rule, _)| rule.matches(info))
+            .map(|(rule, prefix)| (rule, prefix.as_str()))
+    }
+
+    /// This function checks whether any player rule needs the DesktopEntry of players to match.
+    pub fn uses_desktop_entry(&self) -> bool {
+        self.priority_rules
+            .iter()
+            .map(|(rule, _)| rule)
+            .chain(self.prefix_rules.iter().map(|(rule, _)| rule))
+            .any(PlayerRule::uses_desktop_entry)
+    }
+
+    /// This function parses the keys of player_priorities and player_prefixes into rules.
+    /// This should be called once during initialisation; an invalid regular expression is reported as an error.
+    pub fn compile_rules(&mut self) -> Result<(), String> {
+        self.priority_rules = self
+            .player_priorities
+            .iter()
+            .map(|(key, idx)| Ok((PlayerRule::parse(key)?, *idx)))
+            .collect::<Result<_, String>>()?;
+        self.prefix_rules = self
+            .player_prefixes
+            .iter()
+            .filter(|(key, _)| *key != "default")
+            .map(|(key, prefix)| Ok((PlayerRule::parse(key)?, prefix.to_owned())))
+            .collect::<Result<_, String>>()?;
+        Ok(())
+    }
+
+    /// This function returns the Fields to render, depending on whether the alternative layout is toggled on.
+    pub fn active_fields(&self, alt_layout: bool) -> &[Field] {
+        match alt_layout && !self.alt_metadata_fields.is_empty() {
+            true => &self.alt_metadata_fields,
+            false => &self.metadata_fields,
+        }
+    }
+
+    /// This function returns the names of all fields whose value has to be fetched from the active player.
+    /// These are the active Fields, followed by any other fields referenced in the format template.
+    pub fn field_keys(&self, alt_layout: bool) -> Vec<&str> {
+        let mut out: Vec<&str> = self
+            .active_fields(alt_layout)
+            .iter()
+            .map(|f| f.field.as_str())
+            .collect();
+        if let Some(template) = &self.template {
+            for key in template.fields() {
+                if !out.contains(&key) {
+                    out.push(key);
+                }
+            }
+        }
+        out
+    }
+
+    /// This function parses the format string (if any) into a Template, as well as those of all player overrides.
+    /// This should be called once during initialisation; an invalid template is reported as an error.
+    pub fn compile_template(&mut self) -> Result<(), String> {
+        self.template = self
+            .format
+            .as_deref()
+            .map(Template::parse)
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        for (identity, o) in self.player_overrides.iter_mut() {
+            o.template = o
+                .format
+                .as_deref()
+                .map(Template::parse)
+                .transpose()
+                .map_err(|e| format!("player_overrides.{identity}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// This function builds the config in effect for the player with the given identity.
+    ///
+    /// Input:
+    /// identity: Mpris identity of the player.
+    ///
+    /// Returns:
+    /// None if the player has no overrides (and the global config applies as is), the effective config otherwise.
+    pub fn with_override(&self, identity: &str) -> Option<EffectiveConfig> {
+        let o = self.player_overrides.get(identity)?;
+        let mut cfg = self.clone();
+        cfg.player_overrides.clear();
+        if let Some(fields) = &o.metadata_fields {
+            cfg.metadata_fields = fields.clone();
+        }
+        if let Some(separator) = &o.metadata_separator {
+            cfg.metadata_separator = separator.clone();
+        }
+        if let Some(brk) = &o.break_character {
+            cfg.break_character = Some(brk.clone());
+        }
+        if let Some(rating) = &o.rating_icons {
+            cfg.rating_icons = Some(rating.clone());
+        }
+        if o.format.is_some() {
+            cfg.format = o.format.clone();
+            cfg.template = o.template.clone();
+        }
+        Some(EffectiveConfig {
+            identity: identity.to_owned(),
+            ratings: cfg.build_rating_strings(),
+            cfg,
+        })
+    }
+
+    /// This function serialises the config to TOML, as written to the config file.
+    /// This goes through toml::Value, which writes plain values before tables (as TOML requires) regardless of the order of the fields.
+    pub fn to_toml(&self) -> Result<String, String> {
+        let value = toml::Value::try_from(self).map_err(|e| e.to_string())?;
+        toml::to_string_pretty(&value).map_err(|e| e.to_string())
+    }
+
     /// This function builds the pre-computed rating strings for a given Rating_icons field.
     pub fn build_rating_strings(&self) -> Vec<String> {
         match self.rating_icons.as_ref() {
@@ -224,8 +804,8 @@ impl Config {
 
     /// This function returns the default player_priorities, used when a non-existent config file is requested.
     /// The values of these are based on nothing but my own experience; in fact I'm not even sure if the Spotify app's identity is correct.
-    fn default_player_priorities() -> HashMap<String, u8> {
-        let mut out = HashMap::new();
+    fn default_player_priorities() -> IndexMap<String, u8> {
+        let mut out = IndexMap::new();
 
         out.insert("Clementine".to_owned(), 1);
         out.insert("Spotify".to_owned(), 2);
@@ -248,8 +828,8 @@ impl Config {
 
     /// This function returns the default prefixes, used when a non-existent config file is requested.
     /// Like the player priorities function, this is mostly just based on my own experience.
-    fn default_player_prefixes() -> HashMap<String, String> {
-        let mut out: HashMap<String, String> = HashMap::new();
+    fn default_player_prefixes() -> IndexMap<String, String> {
+        let mut out: IndexMap<String, String> = IndexMap::new();
 
         out.insert("chromium".to_owned(), "g".to_owned());
         out.insert("Clementine".to_owned(), "c".to_owned());
@@ -261,4 +841,68 @@ impl Config {
 
         out
     }
+
+    /// This function returns the schema version of config files without a config_version key.
+    fn legacy_config_version() -> u32 {
+        1
+    }
+
+    /// This function returns whether unknown players are accepted when the key is absent.
+    /// They always were before the option existed, so they still are.
+    fn default_accept_unknown_players() -> bool {
+        true
+    }
+
+    /// This function returns the priority of unknown players when the key is absent; lower than any listed player.
+    fn default_unknown_player_priority() -> u8 {
+        u8::MAX
+    }
+
+    /// This function returns the default icon of the player:pinned field, used when the key is absent.
+    fn default_pin_icon() -> String {
+        "*".to_owned()
+    }
+
+    /// This function returns the default volume step, used when the key is absent.
+    fn default_volume_step() -> f64 {
+        0.05
+    }
+
+    /// This function returns the default format of the time fields, used when the key is absent.
+    fn default_time_format() -> String {
+        "{m}:{ss}".to_owned()
+    }
+
+    /// This function returns the default format of the time fields for long tracks, used when the key is absent.
+    fn default_time_format_long() -> String {
+        "{h}:{mm}:{ss}".to_owned()
+    }
+
+    /// This function returns the default time between scroll steps, used when the key is absent.
+    fn default_scroll_step() -> u64 {
+        250
+    }
+
+    /// This function returns the default signal actions, used when a non-existent config file is requested or the table is absent.
+    /// It maps SIGUSR1 to play-pause, which is what that signal has always done.
+    fn default_signal_actions() -> HashMap<String, String> {
+        let mut out: HashMap<String, String> = HashMap::new();
+
+        out.insert("SIGUSR1".to_owned(), "play-pause".to_owned());
+
+        out
+    }
+
+    /// This function returns the default click actions, used when a non-existent config file is requested or the table is absent.
+    fn default_click_actions() -> HashMap<String, String> {
+        let mut out: HashMap<String, String> = HashMap::new();
+
+        out.insert("1".to_owned(), "play-pause".to_owned());
+        out.insert("2".to_owned(), "cycle-next".to_owned());
+        out.insert("3".to_owned(), "next".to_owned());
+        out.insert("4".to_owned(), "volume-up".to_owned());
+        out.insert("5".to_owned(), "volume-down".to_owned());
+
+        out
+    }
 }
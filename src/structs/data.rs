@@ -1,8 +1,104 @@
 //! This file contains structs and functions related to data management within the program.
 //! It effectively contains the state of the program.
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use mpris::Player;
+use mpris::{LoopStatus, PlaybackStatus, Player};
+
+use super::config::{Config, EffectiveConfig};
+
+/// This struct contains the scroll position of one scrolling field.
+pub struct ScrollState {
+    /// Number of characters the string is scrolled by.
+    pub offset: usize,
+    /// Number of scroll steps left to wait before moving again.
+    pub pause: u32,
+}
+
+/// Highest playback rate a position is interpolated at; players reporting more than this are taken to play at this rate.
+pub const MAX_RATE: f64 = 16.0;
+
+/// This struct contains the last position queried from the active player.
+/// The current position is interpolated from it, so the player does not have to be queried every tick.
+pub struct PositionSample {
+    /// Position within the track when the sample was taken.
+    pub position: Duration,
+    /// Length of the track, if known.
+    pub length: Option<Duration>,
+    /// Playback rate (1.0 is normal speed); 0.0 if the player is not playing.
+    pub rate: f64,
+    /// Moment the sample was taken.
+    pub at: Instant,
+}
+
+impl PositionSample {
+    /// This function creates a sample taken now.
+    /// The rate comes straight from the player, so a rate which is not a positive number is taken as 0.0 (not moving),
+    /// and a rate above MAX_RATE as MAX_RATE.
+    pub fn new(position: Duration, length: Option<Duration>, rate: f64) -> Self {
+        let rate = match rate.is_finite() && rate > 0.0 {
+            true => rate.min(MAX_RATE),
+            false => 0.0,
+        };
+        Self {
+            position,
+            length,
+            rate,
+            at: Instant::now(),
+        }
+    }
+
+    /// This function returns the interpolated current position, never exceeding the length of the track.
+    pub fn position(&self) -> Duration {
+        let moved = Duration::try_from_secs_f64(self.at.elapsed().as_secs_f64() * self.rate).unwrap_or_default();
+        let position = self.position.saturating_add(moved);
+        match self.length {
+            Some(length) => position.min(length),
+            None => position,
+        }
+    }
+
+    /// This function returns the interpolated position as a percentage of the length of the track.
+    /// None if the length is unknown or zero.
+    pub fn percentage(&self) -> Option<u8> {
+        let length = self.length.filter(|l| !l.is_zero())?;
+        let percentage = self.position().as_secs_f64() / length.as_secs_f64() * 100.0;
+        Some(percentage.round().clamp(0.0, 100.0) as u8)
+    }
+
+    /// This function returns how long it takes until the interpolated position reaches its' next whole second.
+    /// None if the position is not moving, or so slowly that the next second is too far away to represent.
+    pub fn until_next_second(&self) -> Option<Duration> {
+        if self.rate.is_nan() || self.rate <= 0.0 {
+            return None;
+        }
+        let left = Duration::from_secs(1) - Duration::from_nanos(self.position().subsec_nanos() as u64);
+        Duration::try_from_secs_f64(left.as_secs_f64() / self.rate).ok()
+    }
+}
+
+/// This struct contains which of the buttons (see Config::buttons) the active player supports.
+#[derive(Clone, Copy, Default)]
+pub struct Capabilities {
+    /// Whether the player can go back to the previous track.
+    pub previous: bool,
+    /// Whether the player can be paused (and played).
+    pub pause: bool,
+    /// Whether the player can skip to the next track.
+    pub next: bool,
+}
+
+/// This struct contains what the program remembers about one player between updates.
+/// It is used to break ties between players of equal priority.
+pub struct PlayerActivity {
+    /// Moment the player was first seen.
+    pub first_seen: Instant,
+    /// Moment the player last changed to Playing; None if it has not been seen playing.
+    pub started_playing: Option<Instant>,
+    /// Playback status of the player at the last update.
+    pub status: PlaybackStatus,
+}
 
 /// This struct concerns itself with the current state of the program.
 pub struct Data {
@@ -13,6 +109,95 @@ pub struct Data {
     pub field_text: HashMap<String, String>,
     /// What character to render as prefix.
     pub prefix: String,
+    /// The last line written to stdout, used to avoid printing the same line twice.
+    /// None if nothing has been printed yet.
+    pub last_output: Option<String>,
+    /// Bus name of the player picked at run-time, which takes precedence over the configured priorities.
+    /// Reset to None when that player disappears.
+    pub pinned_player: Option<String>,
+    /// Whether the alternative layout (alt_metadata_fields) is toggled on.
+    pub alt_layout: bool,
+    /// HashMap representing the scroll position of each scrolling field.
+    /// An entry is removed whenever the text of its' field changes, so it starts scrolling from the beginning.
+    pub scroll: HashMap<String, ScrollState>,
+    /// Shell command (minus the action) which sends an action to this instance, used for polybar action tags.
+    /// None if this instance is not listening on a control socket.
+    pub ctl_command: Option<String>,
+    /// Last position sample of the active player; None if no time fields are rendered or the player has no position.
+    pub position: Option<PositionSample>,
+    /// Playback status of the active player, as last seen while selecting it.
+    pub status: Option<PlaybackStatus>,
+    /// Shuffle state of the active player; None if unknown or unsupported.
+    pub shuffle: Option<bool>,
+    /// Loop status of the active player; None if unknown or unsupported.
+    pub loop_status: Option<LoopStatus>,
+    /// Buttons supported by the active player; only queried if buttons are rendered.
+    pub capabilities: Capabilities,
+    /// Config in effect for the active player, if it has overrides. Rebuilt only when the active player's identity changes.
+    pub effective_config: Option<Rc<EffectiveConfig>>,
+    /// State of the other acceptable players rendered in multi-player mode, in order of rank.
+    /// Only the player, prefix, status, field texts and effective config of these are used.
+    pub other_players: Vec<Data>,
+    /// HashMap of bus names of all players seen at the last update (key) to what is known about them (value).
+    pub activity: HashMap<String, PlayerActivity>,
+    /// HashMap of bus names of muted players (key) to the volume they had before being muted (value).
+    pub muted_volumes: HashMap<String, f64>,
+}
+
+impl Data {
+    /// This function checks whether the given unique bus name belongs to the active player.
+    pub fn is_current(&self, unique_name: &str) -> bool {
+        match &self.current_player {
+            Some(p) => p.unique_name() == unique_name,
+            None => false,
+        }
+    }
+
+    /// This function caches the config in effect for the active player, rebuilding it only if the active player's identity changed.
+    /// It is called by update_players whenever the active player is (re)selected.
+    ///
+    /// Input:
+    /// cfg: global Config struct of the program.
+    pub fn resolve_config(&mut self, cfg: &Config) {
+        let identity = self.current_player.as_ref().map(|p| p.identity().to_owned());
+        self.resolve_identity(cfg, identity.as_deref());
+    }
+
+    /// This function caches the config in effect for a player with the given identity; None if there is no active player.
+    fn resolve_identity(&mut self, cfg: &Config, identity: Option<&str>) {
+        let Some(identity) = identity else {
+            self.effective_config = None;
+            return;
+        };
+        let cached = self
+            .effective_config
+            .as_ref()
+            .is_some_and(|e| e.identity == identity);
+        if !cached {
+            self.effective_config = cfg.with_override(identity).map(Rc::new);
+        }
+    }
+
+    /// This function returns the config in effect for the active player, as cached by the last call to resolve_config.
+    pub fn config<'a>(&'a self, cfg: &'a Config) -> &'a Config {
+        match &self.effective_config {
+            Some(e) => &e.cfg,
+            None => cfg,
+        }
+    }
+
+    /// This function runs the given function with the config in effect for the active player, and mutable access to Data.
+    ///
+    /// Input:
+    /// cfg: global Config struct of the program.
+    /// f: function to run.
+    ///
+    /// Returns:
+    /// whatever f returns.
+    pub fn with_config<T>(&mut self, cfg: &Config, f: impl FnOnce(&Config, &mut Data) -> T) -> T {
+        let effective = self.effective_config.clone();
+        f(effective.as_deref().map_or(cfg, |e| &e.cfg), self)
+    }
 }
 
 /// Defaults for Data struct.
@@ -23,6 +208,97 @@ impl Default for Data {
             current_player: None,
             field_text: HashMap::new(),
             prefix: "".to_owned(),
+            last_output: None,
+            pinned_player: None,
+            alt_layout: false,
+            scroll: HashMap::new(),
+            ctl_command: None,
+            position: None,
+            status: None,
+            shuffle: None,
+            loop_status: None,
+            capabilities: Capabilities::default(),
+            effective_config: None,
+            activity: HashMap::new(),
+            other_players: Vec::new(),
+            muted_volumes: HashMap::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use std::rc::Rc;
+
+    use super::{Data, PositionSample, MAX_RATE};
+    use crate::structs::config::{Config, Field, PlayerOverride};
+
+    #[test]
+    fn odd_rates_do_not_panic() {
+        let cases = [
+            // (rate reported by the player, rate used)
+            (1.0, 1.0),
+            (-1.0, 0.0),
+            (f64::NAN, 0.0),
+            (f64::INFINITY, 0.0),
+            (1e300, MAX_RATE),
+            (1e-300, 1e-300),
+        ];
+        for (reported, used) in cases {
+            let sample = PositionSample::new(Duration::from_secs(5), Some(Duration::from_secs(60)), reported);
+            assert_eq!(sample.rate, used, "{reported}");
+            assert!(sample.position() >= Duration::from_secs(5), "{reported}");
+            assert!(sample.position() <= Duration::from_secs(60), "{reported}");
+            // at 1e-300 the next second is too far away to represent.
+            assert_eq!(sample.until_next_second().is_some(), used >= 1.0, "{reported}");
+        }
+    }
+
+    #[test]
+    fn percentage_is_of_the_track_length() {
+        let sample = |length| PositionSample::new(Duration::from_secs(15), length, 0.0).percentage();
+        assert_eq!(sample(Some(Duration::from_secs(60))), Some(25));
+        assert_eq!(sample(Some(Duration::from_secs(10))), Some(100));
+        assert_eq!(sample(Some(Duration::ZERO)), None);
+        assert_eq!(sample(None), None);
+    }
+
+    #[test]
+    fn effective_config_is_rebuilt_when_the_identity_changes() {
+        let mut cfg = Config::default();
+        cfg.player_overrides.insert(
+            "mpv".to_owned(),
+            PlayerOverride {
+                metadata_fields: Some(vec![Field::constructor("xesam:url", 30, None)]),
+                ..Default::default()
+            },
+        );
+        cfg.player_overrides.insert(
+            "Spotify".to_owned(),
+            PlayerOverride {
+                metadata_separator: Some(" / ".to_owned()),
+                ..Default::default()
+            },
+        );
+        let mut data = Data::default();
+
+        data.resolve_identity(&cfg, Some("mpv"));
+        let mpv = Rc::clone(data.effective_config.as_ref().unwrap());
+        assert_eq!(data.config(&cfg).metadata_fields[0].field, "xesam:url");
+        data.resolve_identity(&cfg, Some("mpv"));
+        assert!(Rc::ptr_eq(&mpv, data.effective_config.as_ref().unwrap()));
+
+        data.resolve_identity(&cfg, Some("Spotify"));
+        assert_eq!(data.config(&cfg).metadata_fields[0].field, "xesam:title");
+        assert_eq!(data.config(&cfg).metadata_separator, " / ");
+
+        data.resolve_identity(&cfg, Some("VLC Media Player"));
+        assert!(data.effective_config.is_none());
+        data.resolve_identity(&cfg, Some("mpv"));
+        assert!(!Rc::ptr_eq(&mpv, data.effective_config.as_ref().unwrap()));
+        data.resolve_identity(&cfg, None);
+        assert!(data.effective_config.is_none());
+    }
+}
@@ -0,0 +1,27 @@
+//! This file contains the events which wake up the main loop of the program.
+use std::sync::mpsc::Sender;
+
+use super::action::{Action, Reply};
+
+/// This enum represents something happening outside the main loop that may affect the output.
+/// Each variant carrying a String contains the unique bus name of the player that emitted it.
+pub enum Event {
+    /// An mpris player appeared on or disappeared from the session bus.
+    PlayersChanged,
+    /// A player changed its' PlaybackStatus, which may change which player is active.
+    StatusChanged(String),
+    /// A player changed its' metadata (ie a new track started playing).
+    MetadataChanged(String),
+    /// A player changed a property shown in one of the player:* fields (ie its' volume).
+    PropertiesChanged(String),
+    /// A player jumped to a new position in the current track.
+    Seeked(String),
+    /// An Action was received on the control socket. The Reply should be sent back through the given Sender.
+    Command(Action, Sender<Reply>),
+    /// One of the signals in the signal_actions table was received; contains the number of the signal.
+    Signal(i32),
+    /// A block was clicked in i3bar with the given mouse button.
+    Click(u8),
+    /// The config file was modified, or the process received SIGHUP.
+    ConfigChanged,
+}
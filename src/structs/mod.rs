@@ -1,4 +1,10 @@
 //! This module contains all files which deal with structs used in the program
+pub mod action;
+pub mod cli;
 pub mod config;
 pub mod data;
-pub mod cli;
\ No newline at end of file
+pub mod event;
+pub mod output;
+pub mod player_rule;
+pub mod problem;
+pub mod template;
@@ -0,0 +1,61 @@
+//! This file contains structs representing the output of the program for bars that expect structured data.
+use serde::{Deserialize, Serialize};
+
+/// This struct represents one update of a waybar custom module.
+/// See waybar-custom(5) for the meaning of each key.
+#[derive(Serialize, Default)]
+pub struct WaybarOutput {
+    /// Text shown in the bar.
+    pub text: String,
+    /// Text shown when hovering over the module.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub tooltip: String,
+    /// CSS classes applied to the module.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub class: Vec<String>,
+    /// Alternative text, usable as {alt} in waybar's format strings.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub alt: String,
+    /// Percentage value, usable as {percentage} in waybar's format strings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentage: Option<u8>,
+}
+
+/// This struct represents the header sent once at the start of the i3bar protocol.
+/// See i3bar-protocol(7) for the meaning of each key.
+#[derive(Serialize)]
+pub struct I3barHeader {
+    /// Protocol version; always 1.
+    pub version: u8,
+    /// Whether i3bar should send click events to stdin.
+    pub click_events: bool,
+}
+
+/// This struct represents one block of an i3bar status line.
+/// See i3bar-protocol(7) for the meaning of each key.
+#[derive(Serialize)]
+pub struct I3barBlock {
+    /// Text shown in the bar.
+    pub full_text: String,
+    /// Name of the block, sent back in click events.
+    pub name: String,
+    /// Instance of the block, sent back in click events.
+    pub instance: String,
+    /// Whether full_text contains Pango markup.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub markup: Option<String>,
+}
+
+/// This struct represents one click event sent by i3bar on stdin.
+/// Only the keys used by the program are read.
+#[derive(Deserialize)]
+pub struct I3barClick {
+    /// Name of the clicked block.
+    #[serde(default)]
+    pub name: String,
+    /// Instance of the clicked block.
+    #[serde(default)]
+    pub instance: String,
+    /// Mouse button used (1 = left, 2 = middle, 3 = right, 4/5 = scroll up/down).
+    pub button: u8,
+}
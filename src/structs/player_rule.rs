@@ -0,0 +1,152 @@
+//! This file contains the rules used to match players in the player_priorities and player_prefixes maps.
+//!
+//! Syntax of a rule (the key of an entry):
+//! - `regex:<expression>` matches the identity against a regular expression.
+//! - `bus:<name>` matches the D-Bus bus name; `*` matches any number of characters (`bus:org.mpris.MediaPlayer2.firefox.instance*`).
+//! - `desktop:<name>` matches the DesktopEntry of the player, case-insensitively.
+//! - anything else matches the identity, case-insensitively.
+use mpris::Player;
+use regex::Regex;
+
+/// This struct contains the properties of a player which rules can match on.
+/// It is separate from mpris::Player so matching can be done (and tested) without a session bus.
+pub struct PlayerInfo {
+    /// Mpris identity of the player.
+    pub identity: String,
+    /// Well-known D-Bus bus name of the player.
+    pub bus_name: String,
+    /// DesktopEntry of the player, if it has one (and it was asked for).
+    pub desktop_entry: Option<String>,
+}
+
+impl PlayerInfo {
+    /// This function collects the properties of the given player.
+    ///
+    /// Input:
+    /// player: the player to describe.
+    /// desktop_entry: whether to query the DesktopEntry property, which costs a D-Bus call.
+    pub fn new(player: &Player, desktop_entry: bool) -> Self {
+        Self {
+            identity: player.identity().to_owned(),
+            bus_name: player.bus_name().to_owned(),
+            desktop_entry: match desktop_entry {
+                true => player.get_desktop_entry().ok().flatten(),
+                false => None,
+            },
+        }
+    }
+}
+
+/// This enum describes what part of a player a rule matches on.
+#[derive(Debug, Clone)]
+enum Matcher {
+    /// Identity, compared case-insensitively.
+    Identity(String),
+    /// Identity, matched against the expression.
+    Regex(Regex),
+    /// Bus name, matched against the (anchored) glob converted to an expression.
+    BusName(Regex),
+    /// DesktopEntry, compared case-insensitively.
+    DesktopEntry(String),
+}
+
+/// This struct represents one parsed rule.
+#[derive(Debug, Clone)]
+pub struct PlayerRule {
+    /// The rule as written in the config file.
+    pub key: String,
+    matcher: Matcher,
+}
+
+impl PlayerRule {
+    /// This function parses the key of a player_priorities or player_prefixes entry into a rule.
+    ///
+    /// Input:
+    /// key: the key as written in the config file.
+    ///
+    /// Returns:
+    /// the rule, or a description of why the regular expression is invalid.
+    pub fn parse(key: &str) -> Result<Self, String> {
+        let matcher = if let Some(expr) = key.strip_prefix("regex:") {
+            Matcher::Regex(Regex::new(expr).map_err(|e| format!("{key}: {e}"))?)
+        } else if let Some(glob) = key.strip_prefix("bus:") {
+            let expr = glob
+                .split('*')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(".*");
+            Matcher::BusName(Regex::new(&format!("^{expr}$")).map_err(|e| format!("{key}: {e}"))?)
+        } else if let Some(name) = key.strip_prefix("desktop:") {
+            Matcher::DesktopEntry(name.to_owned())
+        } else {
+            Matcher::Identity(key.to_owned())
+        };
+        Ok(Self {
+            key: key.to_owned(),
+            matcher,
+        })
+    }
+
+    /// This function checks whether the rule matches the given player.
+    pub fn matches(&self, info: &PlayerInfo) -> bool {
+        match &self.matcher {
+            Matcher::Identity(name) => info.identity.eq_ignore_ascii_case(name),
+            Matcher::Regex(re) => re.is_match(&info.identity),
+            Matcher::BusName(re) => re.is_match(&info.bus_name),
+            Matcher::DesktopEntry(name) => info
+                .desktop_entry
+                .as_ref()
+                .is_some_and(|d| d.eq_ignore_ascii_case(name)),
+        }
+    }
+
+    /// This function checks whether the rule needs the DesktopEntry of a player to match.
+    pub fn uses_desktop_entry(&self) -> bool {
+        matches!(self.matcher, Matcher::DesktopEntry(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PlayerInfo, PlayerRule};
+
+    fn firefox() -> PlayerInfo {
+        PlayerInfo {
+            identity: "Mozilla Firefox".to_owned(),
+            bus_name: "org.mpris.MediaPlayer2.firefox.instance_1_42".to_owned(),
+            desktop_entry: Some("firefox".to_owned()),
+        }
+    }
+
+    #[test]
+    fn rules_match_their_part_of_the_player() {
+        let cases = [
+            ("mozilla firefox", true),
+            ("Firefox", false),
+            ("regex:Fire", true),
+            ("regex:^Fire", false),
+            ("bus:org.mpris.MediaPlayer2.firefox.instance*", true),
+            ("bus:org.mpris.MediaPlayer2.firefox", false),
+            ("bus:*.firefox.*", true),
+            ("desktop:Firefox", true),
+            ("desktop:chromium", false),
+        ];
+        for (key, expected) in cases {
+            let rule = PlayerRule::parse(key).unwrap();
+            assert_eq!(rule.matches(&firefox()), expected, "{key}");
+        }
+    }
+
+    #[test]
+    fn glob_characters_are_literal() {
+        let rule = PlayerRule::parse("bus:org.mpris.MediaPlayer2.mpv").unwrap();
+        let mut info = firefox();
+        info.bus_name = "org-mpris-MediaPlayer2-mpv".to_owned();
+        assert!(!rule.matches(&info));
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        assert!(PlayerRule::parse("regex:(").is_err());
+    }
+}
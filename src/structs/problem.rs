@@ -0,0 +1,52 @@
+//! This file contains the struct used to report problems found in a config file by the check subcommand.
+use std::fmt;
+
+/// This enum describes how serious a problem is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The config does not do what it says; the check fails.
+    Error,
+    /// The config works, but likely not as intended.
+    Warning,
+}
+
+/// This struct represents one problem found in the config, along with where it was found.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    /// How serious the problem is.
+    pub severity: Severity,
+    /// Path of the offending key, ie `metadata_fields[0].num_chars`.
+    pub location: String,
+    /// Description of the problem, and possibly how to fix it.
+    pub message: String,
+}
+
+impl Problem {
+    /// This function creates a new problem with Error severity.
+    pub fn error(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+
+    /// This function creates a new problem with Warning severity.
+    pub fn warning(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}: {}: {}", self.location, self.message)
+    }
+}
@@ -0,0 +1,350 @@
+//! This file contains the whole-line output template, which is parsed once at startup.
+//!
+//! Syntax:
+//! - `{name}` is replaced by the value of a field. Names without a namespace refer to xesam fields ({title} is xesam:title),
+//!   except for the special names {prefix} and {status}.
+//! - `{name:20}` truncates the value to 20 characters.
+//! - `{name|text}` renders text if the field has no value.
+//! - `[...]` is a conditional section, which vanishes when any field inside it has no value.
+//! - `\{`, `\}`, `\[`, `\]`, `\|` and `\\` render the character itself.
+//! - polybar formatting tags (`%{...}`) are rendered as is.
+use std::fmt;
+
+/// This struct represents one placeholder in the template.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Placeholder {
+    /// Name of the field, with the xesam namespace added where it was left out.
+    pub field: String,
+    /// Maximum number of characters to render, if any.
+    pub num_chars: Option<usize>,
+    /// Text to render if the field has no value.
+    pub fallback: Option<String>,
+}
+
+/// This enum represents one node of the parsed template.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Node {
+    /// Text which is rendered as is.
+    Text(String),
+    /// A field value.
+    Placeholder(Placeholder),
+    /// A conditional section, rendered only if all placeholders inside have a value.
+    Section(Vec<Node>),
+}
+
+/// This struct represents a parsing error, with the (1-based) column in the template where it occurred.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TemplateError {
+    /// Column of the offending character.
+    pub column: usize,
+    /// Description of what went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+/// This struct represents a parsed template.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Template {
+    /// The top-level nodes of the template.
+    pub nodes: Vec<Node>,
+}
+
+/// Names of placeholders which do not refer to metadata.
+pub const SPECIAL_FIELDS: [&str; 2] = ["prefix", "status"];
+
+/// This function resolves a placeholder name to a field name, adding the xesam namespace where it was left out.
+fn resolve_name(name: &str) -> String {
+    match name.contains(':') || SPECIAL_FIELDS.contains(&name) {
+        true => name.to_owned(),
+        false => format!("xesam:{name}"),
+    }
+}
+
+/// Helper struct keeping track of the position within the template while parsing.
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    src: &'a str,
+}
+
+impl Parser<'_> {
+    /// This function returns the column of the given byte index.
+    fn column(&self, idx: usize) -> usize {
+        self.src[..idx].chars().count() + 1
+    }
+
+    /// This function returns an error at the given byte index.
+    fn error<T>(&self, idx: usize, message: impl Into<String>) -> Result<T, TemplateError> {
+        Err(TemplateError {
+            column: self.column(idx),
+            message: message.into(),
+        })
+    }
+
+    /// This function reads the character following a '\', which is taken literally.
+    fn escaped(&mut self, idx: usize) -> Result<char, TemplateError> {
+        match self.chars.next() {
+            Some((_, c)) if "{}[]|\\".contains(c) => Ok(c),
+            Some((i, c)) => self.error(i, format!("'\\{c}' is not a valid escape sequence")),
+            None => self.error(idx, "template ends with a lone '\\'"),
+        }
+    }
+
+    /// This function reads a polybar formatting tag, after its' opening '%'.
+    ///
+    /// Input:
+    /// idx: byte index of the '%'.
+    fn polybar_tag(&mut self, idx: usize) -> Result<String, TemplateError> {
+        let mut tag = "%".to_owned();
+        for (_, c) in self.chars.by_ref() {
+            tag.push(c);
+            if c == '}' {
+                return Ok(tag);
+            }
+        }
+        self.error(idx, "unclosed polybar tag (missing '}')")
+    }
+
+    /// This function parses nodes until the end of the template, or until the closing ']' of a section.
+    ///
+    /// Input:
+    /// open: byte index of the '[' opening the current section, or None at the top level.
+    fn nodes(&mut self, open: Option<usize>) -> Result<Vec<Node>, TemplateError> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        while let Some((idx, c)) = self.chars.next() {
+            match c {
+                '\\' => text.push(self.escaped(idx)?),
+                '%' if matches!(self.chars.peek(), Some((_, '{'))) => {
+                    text.push_str(&self.polybar_tag(idx)?)
+                }
+                '{' => {
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    nodes.push(Node::Placeholder(self.placeholder(idx)?));
+                }
+                '[' => {
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    nodes.push(Node::Section(self.nodes(Some(idx))?));
+                }
+                ']' => match open {
+                    Some(_) => {
+                        if !text.is_empty() {
+                            nodes.push(Node::Text(text));
+                        }
+                        return Ok(nodes);
+                    }
+                    None => return self.error(idx, "unmatched ']' (use '\\]' for a literal one)"),
+                },
+                '}' => return self.error(idx, "unmatched '}' (use '\\}' for a literal one)"),
+                c => text.push(c),
+            }
+        }
+
+        if let Some(open) = open {
+            return self.error(open, "unclosed section (missing ']')");
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(nodes)
+    }
+
+    /// This function parses the inside of a placeholder, after its' opening '{'.
+    ///
+    /// Input:
+    /// open: byte index of the '{'.
+    fn placeholder(&mut self, open: usize) -> Result<Placeholder, TemplateError> {
+        let mut spec = String::new();
+        let mut fallback: Option<String> = None;
+        loop {
+            match self.chars.next() {
+                Some((_, '}')) => break,
+                Some((idx, '\\')) => {
+                    let c = self.escaped(idx)?;
+                    fallback.as_mut().unwrap_or(&mut spec).push(c);
+                }
+                Some((idx, '{')) if fallback.is_none() => {
+                    return self.error(idx, "placeholders cannot be nested")
+                }
+                Some((_, '|')) if fallback.is_none() => fallback = Some(String::new()),
+                Some((_, c)) => fallback.as_mut().unwrap_or(&mut spec).push(c),
+                None => return self.error(open, "unclosed placeholder (missing '}')"),
+            }
+        }
+
+        let spec = spec.trim();
+        let (name, num_chars) = match spec.rsplit_once(':') {
+            Some((name, width)) if !width.is_empty() && width.chars().all(|c| c.is_ascii_digit()) => {
+                match width.parse::<usize>() {
+                    Ok(0) | Err(_) => {
+                        return self.error(open, format!("invalid width '{width}' for {{{name}}}"))
+                    }
+                    Ok(n) => (name, Some(n)),
+                }
+            }
+            _ => (spec, None),
+        };
+        if name.is_empty() {
+            return self.error(open, "placeholder without a field name");
+        }
+        if name.contains(char::is_whitespace) {
+            return self.error(open, format!("field name '{name}' contains whitespace"));
+        }
+
+        Ok(Placeholder {
+            field: resolve_name(name),
+            num_chars,
+            fallback,
+        })
+    }
+}
+
+impl Template {
+    /// This function parses a template string.
+    ///
+    /// Input:
+    /// src: the template, as written in the config.
+    ///
+    /// Returns:
+    /// the parsed Template, or an error pointing at the first problem.
+    pub fn parse(src: &str) -> Result<Self, TemplateError> {
+        let mut parser = Parser {
+            chars: src.char_indices().peekable(),
+            src,
+        };
+        Ok(Template {
+            nodes: parser.nodes(None)?,
+        })
+    }
+
+    /// This function returns the names of all metadata fields referenced in the template.
+    pub fn fields(&self) -> Vec<&str> {
+        fn collect<'a>(nodes: &'a [Node], out: &mut Vec<&'a str>) {
+            for node in nodes {
+                match node {
+                    Node::Placeholder(p) => {
+                        if !SPECIAL_FIELDS.contains(&p.field.as_str()) && !out.contains(&p.field.as_str()) {
+                            out.push(&p.field)
+                        }
+                    }
+                    Node::Section(nodes) => collect(nodes, out),
+                    Node::Text(_) => (),
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(&self.nodes, &mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placeholder(field: &str, num_chars: Option<usize>, fallback: Option<&str>) -> Node {
+        Node::Placeholder(Placeholder {
+            field: field.to_owned(),
+            num_chars,
+            fallback: fallback.map(str::to_owned),
+        })
+    }
+
+    #[test]
+    fn parses_placeholders_sections_and_text() {
+        let template = Template::parse("{prefix}  {title:30}[ - {artist}][ ({album|Unknown})]").unwrap();
+        assert_eq!(
+            template.nodes,
+            vec![
+                placeholder("prefix", None, None),
+                Node::Text("  ".to_owned()),
+                placeholder("xesam:title", Some(30), None),
+                Node::Section(vec![
+                    Node::Text(" - ".to_owned()),
+                    placeholder("xesam:artist", None, None)
+                ]),
+                Node::Section(vec![
+                    Node::Text(" (".to_owned()),
+                    placeholder("xesam:album", None, Some("Unknown")),
+                    Node::Text(")".to_owned()),
+                ]),
+            ]
+        );
+        assert_eq!(
+            template.fields(),
+            vec!["xesam:title", "xesam:artist", "xesam:album"]
+        );
+    }
+
+    #[test]
+    fn namespaced_names_keep_their_colon() {
+        let template = Template::parse("{xesam:title}{mpris:length:5}").unwrap();
+        assert_eq!(
+            template.nodes,
+            vec![
+                placeholder("xesam:title", None, None),
+                placeholder("mpris:length", Some(5), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn polybar_tags_are_literal() {
+        let template = Template::parse("%{F#f00}{title}%{F-} 100%").unwrap();
+        assert_eq!(
+            template.nodes,
+            vec![
+                Node::Text("%{F#f00}".to_owned()),
+                placeholder("xesam:title", None, None),
+                Node::Text("%{F-} 100%".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_are_literal() {
+        let template = Template::parse("\\[{title|a\\}b}\\]").unwrap();
+        assert_eq!(
+            template.nodes,
+            vec![
+                Node::Text("[".to_owned()),
+                placeholder("xesam:title", None, Some("a}b")),
+                Node::Text("]".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_templates_report_their_column() {
+        let cases = [
+            ("{title", 1, "unclosed placeholder (missing '}')"),
+            ("ab[{title}", 3, "unclosed section (missing ']')"),
+            ("{title}]", 8, "unmatched ']' (use '\\]' for a literal one)"),
+            ("x}", 2, "unmatched '}' (use '\\}' for a literal one)"),
+            ("{}", 1, "placeholder without a field name"),
+            ("{title:0}", 1, "invalid width '0' for {title}"),
+            ("{ti{tle}", 4, "placeholders cannot be nested"),
+            ("{my title}", 1, "field name 'my title' contains whitespace"),
+            ("\\n", 2, "'\\n' is not a valid escape sequence"),
+            ("a%{F#f00", 2, "unclosed polybar tag (missing '}')"),
+        ];
+        for (src, column, message) in cases {
+            assert_eq!(
+                Template::parse(src),
+                Err(TemplateError {
+                    column,
+                    message: message.to_owned()
+                }),
+                "{src}"
+            );
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! This file deals with fitting strings into a number of terminal columns.
+//! Strings are measured in grapheme clusters, each as wide as it is displayed: wide (ie CJK) characters and emoji take 2 columns,
+//! combining marks and zero-width joiners take none and are never split from the character they belong to.
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::structs::config::TruncateMode;
+
+/// This function returns the number of columns the given string takes up when displayed.
+pub fn display_width(str: &str) -> usize {
+    str.graphemes(true).map(UnicodeWidthStr::width).sum()
+}
+
+/// This function returns the number of grapheme clusters (user-perceived characters) in the given string.
+pub fn grapheme_count(str: &str) -> usize {
+    str.graphemes(true).count()
+}
+
+/// This function finds the end of the longest start of a string which fits in the given number of columns.
+///
+/// Input:
+/// str: string to check
+/// width: number of columns available
+///
+/// Returns:
+/// byte index at which to cut the string; always at the boundary of a grapheme cluster.
+fn fit_width(str: &str, width: usize) -> usize {
+    let mut used = 0;
+    for (idx, g) in str.grapheme_indices(true) {
+        used += g.width();
+        if used > width {
+            return idx;
+        }
+    }
+    str.len()
+}
+
+/// This function finds the start of the longest end of a string which fits in the given number of columns.
+///
+/// Input:
+/// str: string to check
+/// width: number of columns available
+///
+/// Returns:
+/// byte index from which to keep the string; always at the boundary of a grapheme cluster.
+fn fit_width_rev(str: &str, width: usize) -> usize {
+    let mut used = 0;
+    for (idx, g) in str.grapheme_indices(true).rev() {
+        used += g.width();
+        if used > width {
+            return idx + g.len();
+        }
+    }
+    0
+}
+
+/// This function cuts the start of a string, which fits in the available width, back to the end of its' last whole word.
+/// If it contains no whole word (ie the title is a single long word), it is returned as is.
+///
+/// Input:
+/// str: the full string.
+/// cut: byte index at which the string would be cut.
+///
+/// Returns:
+/// the start of the string to keep.
+fn word_boundary(str: &str, cut: usize) -> &str {
+    let head = &str[..cut];
+    if str[cut..].starts_with(char::is_whitespace) {
+        return head.trim_end();
+    }
+    let idx = head
+        .grapheme_indices(true)
+        .rev()
+        .find(|(_, g)| g.chars().all(char::is_whitespace))
+        .map_or(0, |(idx, _)| idx);
+    match head[..idx].trim_end() {
+        "" => head,
+        word => word,
+    }
+}
+
+/// This function truncates a single string to the given width, if it exceeds it.
+/// The break string (if any) is inserted where the string was cut, on top of the given width.
+///
+/// Input:
+/// str: mutable string to truncate.
+/// num_chars: maximum width of the string, in columns.
+/// brk: Optional, string to insert where the string is cut.
+/// mode: which part of the string to keep.
+pub fn truncate(str: &mut String, num_chars: usize, brk: Option<&str>, mode: TruncateMode) {
+    if display_width(str) <= num_chars {
+        return;
+    }
+    let brk = brk.unwrap_or_default();
+    *str = match mode {
+        TruncateMode::End => format!("{}{brk}", &str[..fit_width(str, num_chars)]),
+        TruncateMode::Word => format!("{}{brk}", word_boundary(str, fit_width(str, num_chars))),
+        TruncateMode::Start => format!("{brk}{}", str[fit_width_rev(str, num_chars)..].trim_start()),
+        TruncateMode::Middle => {
+            let head = &str[..fit_width(str, num_chars - num_chars / 2)];
+            let tail = &str[fit_width_rev(str, num_chars - display_width(head))..];
+            format!("{}{brk}{}", head.trim_end(), tail.trim_start())
+        }
+    };
+}
+
+/// This function returns the part of a scrolling string currently visible.
+/// The string is treated as a loop of itself followed by the gap, of which a window of `width` columns is taken.
+///
+/// Input:
+/// str: the full string.
+/// gap: string inserted between the end and the start of the loop.
+/// width: number of columns to show.
+/// offset: number of grapheme clusters the window is moved along the loop.
+///
+/// Returns:
+/// the visible window of the string.
+pub fn scroll_window(str: &str, gap: &str, width: usize, offset: usize) -> String {
+    let mut used = 0;
+    str.graphemes(true)
+        .chain(gap.graphemes(true))
+        .cycle()
+        .skip(offset)
+        .take_while(|g| {
+            used += g.width();
+            used <= width
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{display_width, scroll_window, truncate};
+    use crate::structs::config::TruncateMode;
+
+    #[test]
+    fn widths_count_columns() {
+        let cases = [
+            ("Allegro", 7),
+            ("日本語のタイトル", 16),
+            ("👨‍👩‍👧 family", 9),
+            ("🇳🇱", 2),
+            ("Beyonce\u{301}", 7),
+            ("שלום עולם", 9),
+            ("مرحبا", 5),
+        ];
+        for (str, width) in cases {
+            assert_eq!(display_width(str), width, "{str}");
+        }
+    }
+
+    #[test]
+    fn truncation_keeps_clusters_whole() {
+        let cases = [
+            // (input, num_chars, expected)
+            ("Symphony No. 5", 20, "Symphony No. 5"),
+            ("Symphony No. 5", 14, "Symphony No. 5"),
+            ("Symphony No. 5", 10, "Symphony N-"),
+            ("日本語のタイトル", 6, "日本語-"),
+            ("日本語のタイトル", 5, "日本-"),
+            ("👨‍👩‍👧👨‍👩‍👧👨‍👩‍👧", 5, "👨‍👩‍👧👨‍👩‍👧-"),
+            ("🇳🇱🇧🇪", 3, "🇳🇱-"),
+            ("Beyonce\u{301} Knowles", 7, "Beyonce\u{301}-"),
+            ("a\u{301}\u{302}\u{303}bc", 1, "a\u{301}\u{302}\u{303}-"),
+            ("שלום עולם", 6, "שלום ע-"),
+        ];
+        for (input, num_chars, expected) in cases {
+            let mut str = input.to_owned();
+            truncate(&mut str, num_chars, Some("-"), TruncateMode::End);
+            assert_eq!(str, expected, "{input} to {num_chars}");
+        }
+    }
+
+    #[test]
+    fn truncation_modes_keep_their_part() {
+        let title = "Symphony No. 5 - IV. Finale";
+        let cases = [
+            // (input, num_chars, mode, expected)
+            (title, 12, TruncateMode::End, "Symphony No.\u{2026}"),
+            (title, 10, TruncateMode::Start, "\u{2026}IV. Finale"),
+            (title, 15, TruncateMode::Middle, "Symphony\u{2026}Finale"),
+            (title, 12, TruncateMode::Middle, "Sympho\u{2026}Finale"),
+            (title, 12, TruncateMode::Word, "Symphony No.\u{2026}"),
+            (title, 14, TruncateMode::Word, "Symphony No. 5\u{2026}"),
+            (title, 11, TruncateMode::Word, "Symphony\u{2026}"),
+            ("Supercalifragilistic", 5, TruncateMode::Word, "Super\u{2026}"),
+            (" Supercalifragilistic", 5, TruncateMode::Word, " Supe\u{2026}"),
+            ("日本語 タイトル", 9, TruncateMode::Word, "日本語\u{2026}"),
+            ("日本語のタイトル", 8, TruncateMode::Middle, "日本\u{2026}トル"),
+            ("日本語のタイトル", 4, TruncateMode::Start, "\u{2026}トル"),
+            ("שלום עולם", 6, TruncateMode::Word, "שלום\u{2026}"),
+        ];
+        for (input, num_chars, mode, expected) in cases {
+            let mut str = input.to_owned();
+            truncate(&mut str, num_chars, Some("\u{2026}"), mode);
+            assert_eq!(str, expected, "{input} to {num_chars} ({mode:?})");
+        }
+    }
+
+    #[test]
+    fn scrolling_moves_by_clusters() {
+        let cases = [
+            ("abcdef", 3, 0, "abc"),
+            ("abcdef", 3, 5, "f  "),
+            ("日本語のタイトル", 5, 0, "日本"),
+            ("日本語のタイトル", 4, 1, "本語"),
+            ("e\u{301}tude", 3, 0, "e\u{301}tu"),
+            ("👨‍👩‍👧 x", 4, 0, "👨‍👩‍👧 x"),
+        ];
+        for (input, width, offset, expected) in cases {
+            assert_eq!(scroll_window(input, "  ", width, offset), expected, "{input} at {offset}");
+        }
+    }
+}
@@ -1,14 +1,14 @@
 //! This file deals with updating the actual message, including proper formatting.
 use log::{debug, trace};
-use mpris::MetadataValue;
+use mpris::{Metadata, MetadataValue};
 
+use crate::sanitise::strip_control;
 use crate::structs::{config::Config, data::Data};
+use crate::update_player_fields::{update_player_fields, PLAYER_NAMESPACE};
+use crate::update_time::{sample_position, update_time, TIME_NAMESPACE};
 
 /// This function converts a given MetadataValue to a String.
-/// Note that two types of the MetadataValue enum are currently unsupported:
-/// Both the Map and Unsupported types currently lead the program to panic!
-/// The HashMap because I honestly don't know when a metadata value would be encoded as such (and am too lazy to dig through the crate's source code),
-/// The Unsupported type should be self-explanatory.
+/// Maps and unsupported values have no sensible text representation; a player sending those gets an empty string (and a debug message).
 ///
 /// Input:
 /// v: MetadataValue to convert.
@@ -40,10 +40,14 @@ fn value_to_string(v: &MetadataValue, sep: char) -> String {
             out.pop();
             out
         }
-        MetadataValue::Map(_v) => {
-            panic!("unimplemented! TBH i have no clue when a metadataValue would even return this?")
+        MetadataValue::Map(_) => {
+            debug!("value_to_string: ignoring a map value");
+            String::new()
+        }
+        MetadataValue::Unsupported => {
+            debug!("value_to_string: ignoring a value of an unsupported type");
+            String::new()
         }
-        MetadataValue::Unsupported => panic!("Unsupported Metadata type detected!"),
     }
 }
 
@@ -57,17 +61,11 @@ fn value_to_string(v: &MetadataValue, sep: char) -> String {
 ///
 /// Output:
 /// Some(String) if a rating exists, None otherwise.
-fn rating_to_string(r: Option<&MetadataValue>, str: &Vec<String>) -> Option<String> {
+fn rating_to_string(r: Option<&MetadataValue>, str: &[String]) -> Option<String> {
     match r {
         Some(rating) => {
             if let Some(f) = rating.as_f64() {
-                let mut i = (f * 10_f64).round() as i64;
-                if i > 10 {
-                    i = 10
-                }
-                if i < 0 {
-                    i = 0
-                }
+                let i = ((f * 10_f64).round() as i64).clamp(0, 10);
 
                 Some(str[i as usize].to_owned()) //TODO: still inefficient. would be better to note the idx and load it in print_text
             } else {
@@ -82,50 +80,92 @@ fn rating_to_string(r: Option<&MetadataValue>, str: &Vec<String>) -> Option<Stri
     }
 }
 
-/// This higher level function updates the to be output Hashmap of strings.
-/// It does so by querying each metadata field in config to the current player, then updating the Hashmap in Data with the new value(s).
+/// This function sets the output string of a single field.
+/// If the string differs from the previous one the field's scroll position is reset.
+///
+/// Input:
+/// data: mutable Data struct for the program.
+/// key: name of the field.
+/// value: new output string of the field.
+fn set_field_text(data: &mut Data, key: &str, value: String) {
+    if data.field_text.get(key) != Some(&value) {
+        data.scroll.remove(key);
+        data.field_text.insert(key.to_owned(), value);
+    }
+}
+
+/// This function updates the to be output Hashmap of strings from the given metadata.
 /// "xesam:userRating" is treated separately, due to requiring a different output format.
+/// Control characters are stripped from every value, so no title can break the output over multiple lines.
 ///
 /// Input:
 /// cfg: Config struct for the program. Contains the wanted metadata fields.
 /// data: mutable Data struct for the program. Its' Hashmap containing strings is updated.
+/// meta: Metadata of the active player.
 /// ratings: Vec of precomputed rating strings.
-pub fn update_message(cfg: &Config, data: &mut Data, ratings: &Vec<String>) {
-    if let Some(player) = &data.current_player {
-        if let Ok(meta) = player.get_metadata() {
-            for field in &cfg.metadata_fields {
-                let key: &str = field.field.as_ref();
-                if field.field.eq("xesam:userRating") {
-                    if let Some(rating_string) = rating_to_string(meta.get(key), ratings) {
-                        data.field_text.insert(key.to_owned(), rating_string);
-                    } else {
+pub fn update_fields(cfg: &Config, data: &mut Data, meta: &Metadata, ratings: &[String]) {
+    for key in cfg.field_keys(data.alt_layout) {
+        if key.starts_with(TIME_NAMESPACE) || key.starts_with(PLAYER_NAMESPACE) {
+            continue;
+        }
+        if key.eq("xesam:userRating") {
+            if let Some(rating_string) = rating_to_string(meta.get(key), ratings) {
+                set_field_text(data, key, rating_string);
+            } else {
+                data.field_text.remove(key);
+            }
+        } else {
+            match meta.get(key) {
+                Some(value) => {
+                    let value = strip_control(&value_to_string(value, cfg.array_separator));
+                    trace!("update_messages: field {} has value {}", key, value);
+                    set_field_text(data, key, value)
+                }
+                None => {
+                    trace!("update_messages: field {} has no value!", key);
+                    // the template decides for itself what to show for missing fields.
+                    if key.eq("bs:isFavorite") || cfg.template.is_some() {
                         data.field_text.remove(key);
+                        continue;
                     }
-                } else {
-                    match meta.get(&key) {
-                        Some(value) => {
-                            trace!(
-                                "update_messages: field {} has value {}",
-                                key,
-                                value_to_string(value, cfg.array_separator)
-                            );
-                            data.field_text
-                                .insert(key.to_owned(), value_to_string(value, cfg.array_separator))
-                        }
-                        None => {
-                            trace!("update_messages: field {} has no value!", key);
-                            if field.field.eq("bs:isFavorite") {
-                                data.field_text.remove(key);
-                                continue;
-                            }
-                            data.field_text.insert(
-                                key.to_owned(),
-                                format!("No {}", key.trim_start_matches("xesam:")),
-                            )
-                        }
-                    };
+                    set_field_text(
+                        data,
+                        key,
+                        format!("No {}", key.trim_start_matches("xesam:")),
+                    )
                 }
-            }
+            };
+        }
+    }
+}
+
+/// This higher level function updates the to be output Hashmap of strings.
+/// It does so by querying the metadata of the current player, then updating the Hashmap in Data with the new value(s).
+/// The player fields and the position of the player (for the time fields) are updated as well.
+/// Any player_overrides of the current player are applied.
+/// In multi-player mode (unless compact) the same is done for every other rendered player.
+///
+/// Input:
+/// cfg: Config struct for the program. Contains the wanted metadata fields.
+/// data: mutable Data struct for the program. Its' Hashmap containing strings is updated.
+/// ratings: Vec of precomputed rating strings.
+pub fn update_message(cfg: &Config, data: &mut Data, ratings: &[String]) {
+    if cfg.multi_player.as_ref().is_some_and(|m| !m.compact) {
+        for other in data.other_players.iter_mut() {
+            update_message(cfg, other, ratings);
+        }
+    }
+    let effective = data.effective_config.clone();
+    let (cfg, ratings) = match effective.as_deref() {
+        Some(e) => (&e.cfg, e.ratings.as_slice()),
+        None => (cfg, ratings),
+    };
+    if let Some(player) = &data.current_player {
+        if let Ok(meta) = player.get_metadata() {
+            update_fields(cfg, data, &meta, ratings);
+            update_player_fields(cfg, data);
+            sample_position(cfg, data, meta.length());
+            update_time(cfg, data);
         } else {
             debug!(
                 "update_messages: Player {} has no metadata!",
@@ -136,3 +176,52 @@ pub fn update_message(cfg: &Config, data: &mut Data, ratings: &Vec<String>) {
         debug!("update_messages: No player found!");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mpris::{Metadata, MetadataValue};
+
+    use super::update_fields;
+    use crate::structs::{
+        config::Config,
+        data::{Data, ScrollState},
+    };
+
+    #[test]
+    fn odd_values_become_empty_strings() {
+        let cfg = Config::default();
+        let mut data = Data::default();
+        let meta = Metadata::from(HashMap::from([
+            (
+                "xesam:title".to_owned(),
+                MetadataValue::Map(HashMap::from([("a".to_owned(), MetadataValue::I32(1))])),
+            ),
+            ("xesam:artist".to_owned(), MetadataValue::Unsupported),
+        ]));
+        update_fields(&cfg, &mut data, &meta, &cfg.build_rating_strings());
+        assert_eq!(data.field_text["xesam:title"], "");
+        assert_eq!(data.field_text["xesam:artist"], "");
+    }
+
+    #[test]
+    fn changed_text_resets_the_scroll_position() {
+        let cfg = Config::default();
+        let mut data = Data::default();
+        let r = cfg.build_rating_strings();
+        let meta = |title: &str| {
+            Metadata::from(HashMap::from([(
+                "xesam:title".to_owned(),
+                MetadataValue::String(title.to_owned()),
+            )]))
+        };
+        update_fields(&cfg, &mut data, &meta("Allegro"), &r);
+        data.scroll.insert("xesam:title".to_owned(), ScrollState { offset: 4, pause: 0 });
+
+        update_fields(&cfg, &mut data, &meta("Allegro"), &r);
+        assert_eq!(data.scroll["xesam:title"].offset, 4);
+        update_fields(&cfg, &mut data, &meta("Adagio"), &r);
+        assert!(!data.scroll.contains_key("xesam:title"));
+    }
+}
@@ -0,0 +1,212 @@
+//! This file deals with the virtual player:* fields, which show properties of the active player rather than of the track.
+use log::debug;
+
+use crate::structs::{
+    config::{Config, LoopIcons, Output, ProgressBar, ShuffleIcons, StatusIcons},
+    data::{Capabilities, Data},
+};
+
+/// Namespace shared by all player fields.
+pub const PLAYER_NAMESPACE: &str = "player:";
+/// Names of all player fields.
+pub const PLAYER_FIELDS: [&str; 6] = [
+    "player:volume",
+    "player:volume_bar",
+    "player:status",
+    "player:shuffle",
+    "player:loop",
+    "player:pinned",
+];
+
+/// This function renders a volume, as a percentage for player:volume or as a bar for player:volume_bar.
+///
+/// Input:
+/// cfg: Config in effect for the active player, containing the volume_icons.
+/// key: name of the field.
+/// volume: volume of the player, where 1.0 is 100%; players may report more than that.
+fn format_volume(cfg: &Config, key: &str, volume: f64) -> String {
+    match key {
+        "player:volume_bar" => match &cfg.volume_icons {
+            Some(bar) => bar.build_string(volume),
+            None => ProgressBar::default().build_string(volume),
+        },
+        _ => format!("{}%", (volume * 100.0).round()),
+    }
+}
+
+/// This function returns the icon for the state of the active player shown in one of the player:status, player:shuffle,
+/// player:loop and player:pinned fields, from the state last stored in Data.
+///
+/// Input:
+/// cfg: Config in effect for the active player, containing the icons.
+/// data: Data struct containing the state of the active player.
+/// key: name of the field.
+///
+/// Returns:
+/// the icon; None if the state is unknown (or the player is not pinned, for player:pinned).
+fn state_icon(cfg: &Config, data: &Data, key: &str) -> Option<String> {
+    match key {
+        "player:status" => data.status.map(|s| match &cfg.status_icons {
+            Some(icons) => icons.icon(s).to_owned(),
+            None => StatusIcons::default().icon(s).to_owned(),
+        }),
+        "player:shuffle" => data.shuffle.map(|s| match &cfg.shuffle_icons {
+            Some(icons) => icons.icon(s).to_owned(),
+            None => ShuffleIcons::default().icon(s).to_owned(),
+        }),
+        "player:pinned" => data.pinned_player.as_ref().map(|_| cfg.pin_icon.clone()),
+        "player:loop" => data.loop_status.map(|s| match &cfg.loop_icons {
+            Some(icons) => icons.icon(s).to_owned(),
+            None => LoopIcons::default().icon(s).to_owned(),
+        }),
+        _ => {
+            debug!("update_player_fields: unknown player field {key}");
+            None
+        }
+    }
+}
+
+/// This function updates the output strings of all rendered player fields, by querying the active player.
+/// The shuffle and loop status of the player (if rendered) and which buttons it supports (if buttons are rendered) are refreshed in Data along the way;
+/// the playback status is kept by update_players. Nothing else is queried, as every query is a D-Bus round trip.
+///
+/// Input:
+/// cfg: Config in effect for the active player.
+/// data: mutable Data struct for the program.
+pub fn update_player_fields(cfg: &Config, data: &mut Data) {
+    let keys: Vec<&str> = cfg
+        .field_keys(data.alt_layout)
+        .into_iter()
+        .filter(|key| key.starts_with(PLAYER_NAMESPACE))
+        .collect();
+    let Some(player) = &data.current_player else {
+        data.shuffle = None;
+        data.loop_status = None;
+        data.capabilities = Capabilities::default();
+        return;
+    };
+    data.shuffle = match keys.contains(&"player:shuffle") {
+        true => player.checked_get_shuffle().ok().flatten(),
+        false => None,
+    };
+    data.loop_status = match keys.contains(&"player:loop") {
+        true => player.checked_get_loop_status().ok().flatten(),
+        false => None,
+    };
+    data.capabilities = match (cfg.output, &cfg.buttons) {
+        (Output::Polybar, Some(_)) => Capabilities {
+            previous: player.can_go_previous().unwrap_or(false),
+            pause: player.can_pause().unwrap_or(false),
+            next: player.can_go_next().unwrap_or(false),
+        },
+        _ => Capabilities::default(),
+    };
+
+    for key in keys {
+        let value = match key {
+            "player:volume" | "player:volume_bar" => player.get_volume().ok().map(|v| format_volume(cfg, key, v)),
+            _ => state_icon(cfg, data, key),
+        };
+        match value {
+            Some(value) => data.field_text.insert(key.to_owned(), value),
+            None => data.field_text.remove(key),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mpris::{LoopStatus, PlaybackStatus};
+
+    use super::{format_volume, state_icon};
+    use crate::structs::{
+        config::{Config, LoopIcons, ProgressBar, ShuffleIcons, StatusIcons},
+        data::Data,
+    };
+
+    #[test]
+    fn volumes_render_as_percentage_and_bar() {
+        let cfg = Config {
+            volume_icons: Some(ProgressBar {
+                width: 4,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let cases = [
+            // (volume, player:volume, player:volume_bar)
+            (0.0, "0%", "----"),
+            (0.125, "13%", "=---"),
+            (0.5, "50%", "##--"),
+            (1.0, "100%", "####"),
+            (1.5, "150%", "####"),
+        ];
+        for (volume, percentage, bar) in cases {
+            assert_eq!(format_volume(&cfg, "player:volume", volume), percentage);
+            assert_eq!(format_volume(&cfg, "player:volume_bar", volume), bar);
+        }
+        assert_eq!(format_volume(&Config::default(), "player:volume_bar", 0.5), "#####-----");
+    }
+
+    #[test]
+    fn states_map_to_their_icons() {
+        let configured = Config {
+            status_icons: Some(StatusIcons {
+                playing: "\u{25b6}".to_owned(),
+                paused: "\u{23f8}".to_owned(),
+                stopped: "\u{23f9}".to_owned(),
+            }),
+            shuffle_icons: Some(ShuffleIcons {
+                on: "\u{1f500}".to_owned(),
+                off: "".to_owned(),
+            }),
+            loop_icons: Some(LoopIcons {
+                none: "".to_owned(),
+                track: "\u{1f502}".to_owned(),
+                playlist: "\u{1f501}".to_owned(),
+            }),
+            ..Default::default()
+        };
+        let status = [
+            // (status, default icon, configured icon)
+            (PlaybackStatus::Playing, ">", "\u{25b6}"),
+            (PlaybackStatus::Paused, "||", "\u{23f8}"),
+            (PlaybackStatus::Stopped, "[]", "\u{23f9}"),
+        ];
+        for (s, default, icon) in status {
+            let data = Data {
+                status: Some(s),
+                ..Default::default()
+            };
+            assert_eq!(state_icon(&Config::default(), &data, "player:status").as_deref(), Some(default));
+            assert_eq!(state_icon(&configured, &data, "player:status").as_deref(), Some(icon));
+        }
+        let shuffle = [(true, "S", "\u{1f500}"), (false, "-", "")];
+        for (s, default, icon) in shuffle {
+            let data = Data {
+                shuffle: Some(s),
+                ..Default::default()
+            };
+            assert_eq!(state_icon(&Config::default(), &data, "player:shuffle").as_deref(), Some(default));
+            assert_eq!(state_icon(&configured, &data, "player:shuffle").as_deref(), Some(icon));
+        }
+        let loops = [
+            (LoopStatus::None, "-", ""),
+            (LoopStatus::Track, "T", "\u{1f502}"),
+            (LoopStatus::Playlist, "P", "\u{1f501}"),
+        ];
+        for (s, default, icon) in loops {
+            let data = Data {
+                loop_status: Some(s),
+                ..Default::default()
+            };
+            assert_eq!(state_icon(&Config::default(), &data, "player:loop").as_deref(), Some(default));
+            assert_eq!(state_icon(&configured, &data, "player:loop").as_deref(), Some(icon));
+        }
+
+        // unknown states leave the field empty.
+        for key in ["player:status", "player:shuffle", "player:loop", "player:pinned", "player:colour"] {
+            assert_eq!(state_icon(&configured, &Data::default(), key), None, "{key}");
+        }
+    }
+}
@@ -1,66 +1,368 @@
 //! This file deals with updating the active player.
 //! It also updates the prefix, which kind of breaks seperation of concerns, but this saves me a lot of headache so I'm not changing it.
-use std::collections::BTreeMap;
+use std::cmp::{Ordering, Reverse};
+use std::collections::HashMap;
+use std::time::Instant;
 
-use crate::structs::{config::Config, data::Data};
+use crate::structs::{
+    config::{Config, SelectionPolicy, TieBreaker},
+    data::{Data, PlayerActivity},
+    player_rule::PlayerInfo,
+};
 use log::{debug, trace};
-use mpris::PlayerFinder;
+use mpris::{PlaybackStatus, Player, PlayerFinder};
 
-/// This function updates the current prefix.
-/// If no entry is found in config containing the active player, a default value is used instead ('>').
+/// This struct contains everything needed to rank one player, without needing the player itself.
+pub struct Candidate {
+    /// Well-known bus name of the player.
+    pub bus_name: String,
+    /// Priority of the player; None if the player is not acceptable.
+    pub priority: Option<u8>,
+    /// Playback status of the player.
+    pub status: PlaybackStatus,
+}
+
+/// This function finds the prefix of a player.
+/// If no rule in config matches the player, a default value is used instead ('>').
 ///
 /// Input:
-/// cfg: Config struct for the program, containing the hashmap of prefixes.
-/// data: mutable char containing the active prefix.
-/// name: name of active player, to fetch the appropriate prefix from cfg.
-fn update_prefix(cfg: &Config, data: &mut Data, name: &str) {
-    if let Some(char) = cfg.player_prefixes.get(name) {
-        data.prefix = char.to_owned();
-        trace!("updated prefix to {}", data.prefix);
+/// cfg: Config struct for the program, containing the rules for prefixes.
+/// info: properties of the player, to fetch the appropriate prefix from cfg.
+fn find_prefix(cfg: &Config, info: &PlayerInfo) -> String {
+    if let Some((rule, prefix)) = cfg.match_prefix(info) {
+        trace!("prefix of {} is {prefix} (rule {})", info.identity, rule.key);
+        prefix.to_owned()
     } else {
-        data.prefix = cfg.player_prefixes.get("default").unwrap().to_owned();
-        trace!("set prefix to default ({})", data.prefix);
+        let prefix = cfg.player_prefixes.get("default").unwrap();
+        trace!("prefix of {} is the default ({prefix})", info.identity);
+        prefix.to_owned()
+    }
+}
+
+/// This function updates the states of the players shown next to the active one in multi-player mode.
+/// The state of a player which was shown before is kept, so its' effective config does not have to be rebuilt.
+///
+/// Input:
+/// cfg: Config struct of the program.
+/// data: mutable Data struct of the program.
+/// others: the other acceptable players and their properties, in order of rank.
+fn update_others(cfg: &Config, data: &mut Data, others: Vec<(PlayerInfo, Player, PlaybackStatus)>) {
+    let Some(multi) = &cfg.multi_player else {
+        data.other_players.clear();
+        return;
+    };
+    let max = match multi.compact {
+        true => usize::MAX,
+        false => (multi.max_players as usize).saturating_sub(1),
+    };
+    let mut previous = std::mem::take(&mut data.other_players);
+    for (info, player, status) in others.into_iter().take(max) {
+        let mut other = match previous.iter().position(|o| o.is_current(player.unique_name())) {
+            Some(idx) => previous.swap_remove(idx),
+            None => Data::default(),
+        };
+        other.prefix = find_prefix(cfg, &info);
+        other.status = Some(status);
+        other.alt_layout = data.alt_layout;
+        other.current_player = Some(player);
+        other.resolve_config(cfg);
+        data.other_players.push(other);
+    }
+}
+
+/// This function updates the remembered activity of every player, and forgets players which are gone.
+///
+/// Input:
+/// activity: HashMap of remembered activity, keyed by bus name.
+/// candidates: all players currently present.
+/// now: moment of the update.
+pub fn update_activity(
+    activity: &mut HashMap<String, PlayerActivity>,
+    candidates: &[Candidate],
+    now: Instant,
+) {
+    activity.retain(|bus_name, _| candidates.iter().any(|c| &c.bus_name == bus_name));
+    for c in candidates {
+        let entry = activity
+            .entry(c.bus_name.clone())
+            .or_insert_with(|| PlayerActivity {
+                first_seen: now,
+                started_playing: None,
+                status: PlaybackStatus::Stopped,
+            });
+        if c.status == PlaybackStatus::Playing && entry.status != PlaybackStatus::Playing {
+            entry.started_playing = Some(now);
+        }
+        entry.status = c.status;
+    }
+}
+
+/// This function compares two candidates of equal priority according to the configured tie breaker.
+/// Candidates equal under the tie breaker are ordered by bus name, so the outcome never depends on the order players were found in.
+fn break_tie(
+    cfg: &Config,
+    activity: &HashMap<String, PlayerActivity>,
+    a: &Candidate,
+    b: &Candidate,
+) -> Ordering {
+    let (ta, tb) = (activity.get(&a.bus_name), activity.get(&b.bus_name));
+    let ord = match cfg.tie_breaker {
+        TieBreaker::MostRecentlyPlaying => Reverse(ta.and_then(|t| t.started_playing))
+            .cmp(&Reverse(tb.and_then(|t| t.started_playing))),
+        TieBreaker::FirstSeen => ta.map(|t| t.first_seen).cmp(&tb.map(|t| t.first_seen)),
+        TieBreaker::BusName => Ordering::Equal,
+    };
+    ord.then_with(|| a.bus_name.cmp(&b.bus_name))
+}
+
+/// This function returns the key by which the selection policy ranks a candidate; lower keys rank higher.
+/// The key consists of the playback tier, the moment the player started playing (for most-recent only) and the priority.
+fn rank_key(
+    policy: SelectionPolicy,
+    activity: &HashMap<String, PlayerActivity>,
+    c: &Candidate,
+) -> (u8, Reverse<Option<Instant>>, Option<u8>) {
+    let tier = match (policy, c.status) {
+        (_, PlaybackStatus::Playing) => 0,
+        (SelectionPolicy::Strict | SelectionPolicy::Sticky, PlaybackStatus::Paused) => 0,
+        (_, PlaybackStatus::Paused) => 1,
+        (_, PlaybackStatus::Stopped) => 2,
+    };
+    let started = match policy {
+        SelectionPolicy::MostRecent => activity.get(&c.bus_name).and_then(|a| a.started_playing),
+        _ => None,
+    };
+    (tier, Reverse(started), c.priority)
+}
+
+/// This function ranks the acceptable candidates according to the selection policy, best first.
+/// Candidates which rank equal are ordered by the tie breaker.
+/// Every acceptable candidate takes part, so two players with the same priority can never hide one another.
+///
+/// Input:
+/// cfg: Config struct of the program.
+/// activity: remembered activity of the players, see update_activity.
+/// candidates: all players currently present.
+///
+/// Returns:
+/// indices of the acceptable candidates, in order of rank.
+pub fn rank_players(
+    cfg: &Config,
+    activity: &HashMap<String, PlayerActivity>,
+    candidates: &[Candidate],
+) -> Vec<usize> {
+    let mut out: Vec<usize> = (0..candidates.len())
+        .filter(|idx| candidates[*idx].priority.is_some())
+        .collect();
+    out.sort_by(|a, b| {
+        let (a, b) = (&candidates[*a], &candidates[*b]);
+        rank_key(cfg.selection_policy, activity, a)
+            .cmp(&rank_key(cfg.selection_policy, activity, b))
+            .then_with(|| break_tie(cfg, activity, a, b))
+    });
+    out
+}
+
+/// This function selects the best of the given candidates, see rank_players.
+/// With the sticky policy the active player is kept for as long as it is acceptable and not stopped.
+///
+/// Input:
+/// cfg: Config struct of the program.
+/// activity: remembered activity of the players, see update_activity.
+/// candidates: all players currently present.
+/// current: bus name of the player which is active now, if any; kept by the sticky policy.
+///
+/// Returns:
+/// index of the selected candidate, or None if no candidate is acceptable.
+pub fn select_player(
+    cfg: &Config,
+    activity: &HashMap<String, PlayerActivity>,
+    candidates: &[Candidate],
+    current: Option<&str>,
+) -> Option<usize> {
+    if cfg.selection_policy == SelectionPolicy::Sticky {
+        let kept = candidates.iter().position(|c| {
+            Some(c.bus_name.as_str()) == current
+                && c.priority.is_some()
+                && c.status != PlaybackStatus::Stopped
+        });
+        if kept.is_some() {
+            return kept;
+        }
     }
+    rank_players(cfg, activity, candidates).first().copied()
 }
 
 /// This function updates which player is selected as 'active'.
-/// It only considers players present in the config.player_priorities field to be valid candidates, then selects the active one with the highest rating.
+/// If a player was picked at run-time and is still around, it is selected regardless of priorities.
+/// Otherwise the acceptable players are ranked by select_player, according to the selection policy.
 /// If none of the acceptable players are available, current_player is set to None instead.
+/// The playback status of the selected player is kept in Data, for use in the player:status field and the output.
+/// In multi-player mode the other acceptable players are kept in Data as well, in order of rank.
+/// The config in effect for each of the players is resolved along the way.
 ///
 /// Input:
 /// pf: PlayerFinder instance of the program.
 /// cfg: Config struct of the program, containing the list of acceptable players.
 /// data: mutable Data struct of the program, containing a marker for the currently active player.
 pub fn update_players(pf: &PlayerFinder, cfg: &Config, data: &mut Data) {
-    // get all acceptable players
-    let players = pf.find_all().unwrap_or(Vec::new());
-    if players.is_empty() {
-        data.current_player = None;
-        debug!("update_players: no players found!")
-    } else {
-        let mut trees = vec![BTreeMap::new(), BTreeMap::new()];
-        for player in players {
-            if let Ok(status) = player.get_playback_status() {
-                let idx = cfg.find_player_priorities_idx(player.identity());
-                match status {
-                    mpris::PlaybackStatus::Playing => trees[0].insert(idx, player),
-                    mpris::PlaybackStatus::Paused => trees[0].insert(idx, player),
-                    mpris::PlaybackStatus::Stopped => trees[1].insert(idx, player),
-                };
-            }
+    let mut players = Vec::new();
+    let mut candidates = Vec::new();
+    for player in pf.find_all().unwrap_or_default() {
+        if let Ok(status) = player.get_playback_status() {
+            let info = PlayerInfo::new(&player, cfg.uses_desktop_entry());
+            candidates.push(Candidate {
+                bus_name: info.bus_name.clone(),
+                priority: cfg.find_player_priorities_idx(&info),
+                status,
+            });
+            players.push((info, player));
         }
+    }
+    update_activity(&mut data.activity, &candidates, Instant::now());
 
-        // select the player with the highest priority.
-        for mut tree in trees {
-            if let Some((_, player)) = tree.pop_first() {
-                update_prefix(cfg, data, player.identity());
-                debug!("update_players: updated player to {}!", player.identity());
-                data.current_player = Some(player);
-                break;
-            } else {
-                data.current_player = None;
-                debug!("update_players: No acceptable player found!");
+    let mut selected = None;
+    if let Some(pinned) = &data.pinned_player {
+        match candidates.iter().position(|c| &c.bus_name == pinned) {
+            Some(idx) => {
+                trace!("update_players: keeping picked player {pinned}");
+                selected = Some(idx);
             }
+            None => {
+                debug!("update_players: picked player {pinned} disappeared!");
+                data.pinned_player = None;
+            }
+        }
+    }
+    let current = data.current_player.as_ref().map(|p| p.bus_name());
+    let selected = selected.or_else(|| select_player(cfg, &data.activity, &candidates, current));
+
+    let mut players: Vec<_> = players.into_iter().map(Some).collect();
+    match selected.and_then(|idx| players[idx].take()) {
+        Some((info, player)) => {
+            data.prefix = find_prefix(cfg, &info);
+            debug!("update_players: updated player to {}!", info.identity);
+            data.status = selected.map(|idx| candidates[idx].status);
+            data.current_player = Some(player);
+        }
+        None => {
+            data.current_player = None;
+            data.status = None;
+            debug!("update_players: No acceptable player found!");
+        }
+    }
+
+    let others = rank_players(cfg, &data.activity, &candidates)
+        .into_iter()
+        .filter_map(|idx| {
+            let (info, player) = players[idx].take()?;
+            Some((info, player, candidates[idx].status))
+        })
+        .collect();
+    update_others(cfg, data, others);
+    data.resolve_config(cfg);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use mpris::PlaybackStatus;
+
+    use super::{select_player, update_activity, Candidate};
+    use crate::structs::config::{Config, SelectionPolicy, TieBreaker};
+
+    fn candidate(bus_name: &str, priority: Option<u8>, status: PlaybackStatus) -> Candidate {
+        Candidate {
+            bus_name: bus_name.to_owned(),
+            priority,
+            status,
         }
     }
+
+    #[test]
+    fn equal_priorities_do_not_hide_each_other() {
+        let cfg = Config {
+            tie_breaker: TieBreaker::BusName,
+            ..Default::default()
+        };
+        let candidates = [
+            candidate("org.mpris.MediaPlayer2.firefox.instance2", Some(5), PlaybackStatus::Paused),
+            candidate("org.mpris.MediaPlayer2.firefox.instance1", Some(5), PlaybackStatus::Paused),
+        ];
+        assert_eq!(select_player(&cfg, &HashMap::new(), &candidates, None), Some(1));
+    }
+
+    #[test]
+    fn stopped_players_rank_last_and_unacceptable_players_never() {
+        let cfg = Config::default();
+        let candidates = [
+            candidate("a", None, PlaybackStatus::Playing),
+            candidate("b", Some(1), PlaybackStatus::Stopped),
+            candidate("c", Some(9), PlaybackStatus::Paused),
+        ];
+        assert_eq!(select_player(&cfg, &HashMap::new(), &candidates, None), Some(2));
+        assert_eq!(select_player(&cfg, &HashMap::new(), &candidates[..1], None), None);
+    }
+
+    #[test]
+    fn tie_breakers_use_activity() {
+        let start = Instant::now();
+        let mut activity = HashMap::new();
+        let mut candidates = vec![
+            candidate("a", Some(3), PlaybackStatus::Paused),
+            candidate("b", Some(3), PlaybackStatus::Paused),
+        ];
+        update_activity(&mut activity, &candidates[..1], start);
+        update_activity(&mut activity, &candidates, start + Duration::from_secs(1));
+        candidates[1].status = PlaybackStatus::Playing;
+        update_activity(&mut activity, &candidates, start + Duration::from_secs(2));
+        candidates[0].status = PlaybackStatus::Playing;
+        update_activity(&mut activity, &candidates, start + Duration::from_secs(3));
+
+        let mut cfg = Config {
+            tie_breaker: TieBreaker::FirstSeen,
+            ..Default::default()
+        };
+        assert_eq!(select_player(&cfg, &activity, &candidates, None), Some(0));
+        cfg.tie_breaker = TieBreaker::MostRecentlyPlaying;
+        assert_eq!(select_player(&cfg, &activity, &candidates, None), Some(0));
+        candidates[1].status = PlaybackStatus::Paused;
+        update_activity(&mut activity, &candidates, start + Duration::from_secs(4));
+        candidates[1].status = PlaybackStatus::Playing;
+        update_activity(&mut activity, &candidates, start + Duration::from_secs(5));
+        assert_eq!(select_player(&cfg, &activity, &candidates, None), Some(1));
+    }
+
+    #[test]
+    fn selection_policies() {
+        let start = Instant::now();
+        let mut activity = HashMap::new();
+        let mut candidates = vec![
+            candidate("clementine", Some(1), PlaybackStatus::Paused),
+            candidate("firefox", Some(5), PlaybackStatus::Playing),
+            candidate("mpv", Some(3), PlaybackStatus::Stopped),
+        ];
+        update_activity(&mut activity, &candidates, start);
+        let mut cfg = Config {
+            selection_policy: SelectionPolicy::Strict,
+            ..Default::default()
+        };
+        assert_eq!(select_player(&cfg, &activity, &candidates, None), Some(0));
+        cfg.selection_policy = SelectionPolicy::PlayingBeforePaused;
+        assert_eq!(select_player(&cfg, &activity, &candidates, None), Some(1));
+
+        candidates[2].status = PlaybackStatus::Playing;
+        update_activity(&mut activity, &candidates, start + Duration::from_secs(1));
+        assert_eq!(select_player(&cfg, &activity, &candidates, None), Some(2));
+        cfg.selection_policy = SelectionPolicy::MostRecent;
+        assert_eq!(select_player(&cfg, &activity, &candidates, None), Some(2));
+
+        cfg.selection_policy = SelectionPolicy::Sticky;
+        assert_eq!(select_player(&cfg, &activity, &candidates, Some("firefox")), Some(1));
+        candidates[1].status = PlaybackStatus::Stopped;
+        assert_eq!(select_player(&cfg, &activity, &candidates, Some("firefox")), Some(0));
+        assert_eq!(select_player(&cfg, &activity, &candidates, Some("gone")), Some(0));
+    }
 }
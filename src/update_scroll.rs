@@ -0,0 +1,144 @@
+//! This file deals with moving scrolling fields along.
+use log::trace;
+
+use crate::structs::{
+    config::{Config, Field, Overflow},
+    data::{Data, ScrollState},
+};
+use crate::truncate_text::{display_width, grapheme_count};
+
+/// This function checks whether the given field currently needs to scroll.
+/// That is the case if it is configured to scroll and its' output exceeds num_chars.
+///
+/// Input:
+/// field: the Field to check.
+/// data: Data struct containing the output strings.
+fn needs_scroll(field: &Field, data: &Data) -> bool {
+    field.overflow == Overflow::Scroll
+        && data
+            .field_text
+            .get(&field.field)
+            .is_some_and(|s| display_width(s) > field.num_chars as usize)
+}
+
+/// This function checks whether the fields of the other players are rendered, ie in multi-player mode unless it is compact.
+fn renders_others(cfg: &Config) -> bool {
+    cfg.multi_player.as_ref().is_some_and(|m| !m.compact)
+}
+
+/// This function checks whether any of the fields of a single player is currently scrolling.
+fn player_is_scrolling(cfg: &Config, data: &Data) -> bool {
+    let cfg = data.config(cfg);
+    data.current_player.is_some()
+        && cfg
+            .active_fields(data.alt_layout)
+            .iter()
+            .any(|f| needs_scroll(f, data))
+}
+
+/// This function checks whether any of the rendered fields is currently scrolling, of the active player or of the other rendered players.
+/// The main loop uses this to decide whether it needs to wake up every scroll_step.
+pub fn is_scrolling(cfg: &Config, data: &Data) -> bool {
+    player_is_scrolling(cfg, data)
+        || (renders_others(cfg) && data.other_players.iter().any(|o| player_is_scrolling(cfg, o)))
+}
+
+/// This function moves every scrolling field of the active player, and of the other rendered players, one character (grapheme cluster) along.
+/// Once a string has scrolled past its' end (and the gap), it starts over and waits for scroll_pause steps.
+///
+/// Input:
+/// cfg: global Config struct for the program; each player uses the config in effect for it.
+/// data: mutable Data struct containing the scroll positions.
+pub fn update_scroll(cfg: &Config, data: &mut Data) {
+    if renders_others(cfg) {
+        for other in data.other_players.iter_mut() {
+            other.with_config(cfg, scroll_fields);
+        }
+    }
+    data.with_config(cfg, scroll_fields);
+}
+
+/// This function moves every scrolling field of a single player along.
+///
+/// Input:
+/// cfg: Config in effect for the player.
+/// data: mutable Data struct of the player, containing the scroll positions.
+fn scroll_fields(cfg: &Config, data: &mut Data) {
+    for field in cfg.active_fields(data.alt_layout) {
+        if !needs_scroll(field, data) {
+            continue;
+        }
+        let len = grapheme_count(&data.field_text[&field.field]) + grapheme_count(&field.scroll_gap);
+
+        let state = data
+            .scroll
+            .entry(field.field.to_owned())
+            .or_insert(ScrollState {
+                offset: 0,
+                pause: field.scroll_pause,
+            });
+        if state.pause > 0 {
+            state.pause -= 1;
+        } else {
+            state.offset = (state.offset + 1) % len;
+            if state.offset == 0 {
+                state.pause = field.scroll_pause;
+            }
+        }
+        trace!("update_scroll: {} at offset {}", field.field, state.offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scroll_fields;
+    use crate::structs::{
+        config::{Config, Field, Overflow},
+        data::Data,
+    };
+
+    /// This function returns a config with a single scrolling field of 3 characters, and data with a 6 character title.
+    /// Along with the " | " gap, the title scrolls around in 9 steps.
+    fn scrolling(scroll_pause: u32) -> (Config, Data) {
+        let cfg = Config {
+            metadata_fields: vec![Field {
+                overflow: Overflow::Scroll,
+                scroll_gap: " | ".to_owned(),
+                scroll_pause,
+                ..Field::constructor("xesam:title", 3, None)
+            }],
+            ..Default::default()
+        };
+        let mut data = Data::default();
+        data.field_text.insert("xesam:title".to_owned(), "abcdef".to_owned());
+        (cfg, data)
+    }
+
+    /// This function scrolls one step, and returns the offset of the title afterwards.
+    fn step(cfg: &Config, data: &mut Data) -> usize {
+        scroll_fields(cfg, data);
+        data.scroll["xesam:title"].offset
+    }
+
+    #[test]
+    fn scrolling_waits_for_the_pause_at_the_start() {
+        let (cfg, mut data) = scrolling(2);
+        let offsets: Vec<_> = (0..4).map(|_| step(&cfg, &mut data)).collect();
+        assert_eq!(offsets, vec![0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn scrolling_wraps_after_the_gap() {
+        let (cfg, mut data) = scrolling(1);
+        let offsets: Vec<_> = (0..13).map(|_| step(&cfg, &mut data)).collect();
+        assert_eq!(offsets, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn short_text_does_not_scroll() {
+        let (cfg, mut data) = scrolling(0);
+        data.field_text.insert("xesam:title".to_owned(), "abc".to_owned());
+        scroll_fields(&cfg, &mut data);
+        assert!(data.scroll.is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+//! This file deals with the virtual time:* fields, which show the position within the current track.
+//! The position is queried from the player only when something happened (a new track, a status change or a seek),
+//! and interpolated locally in between.
+use std::time::Duration;
+
+use log::{debug, trace};
+use mpris::PlaybackStatus;
+
+use crate::structs::{
+    config::{Config, Output, ProgressBar},
+    data::{Data, PositionSample},
+};
+
+/// Namespace shared by all time fields.
+pub const TIME_NAMESPACE: &str = "time:";
+/// Names of all time fields.
+pub const TIME_FIELDS: [&str; 4] = ["time:position", "time:length", "time:remaining", "time:progress"];
+
+/// This function checks whether any time field is currently rendered.
+fn shows_time(cfg: &Config, data: &Data) -> bool {
+    cfg.field_keys(data.alt_layout)
+        .iter()
+        .any(|key| key.starts_with(TIME_NAMESPACE))
+}
+
+/// This function formats a duration according to the configured time formats.
+///
+/// Input:
+/// cfg: Config struct for the program, containing the time formats.
+/// d: the duration to format.
+///
+/// Returns:
+/// the formatted duration.
+fn format_time(cfg: &Config, d: Duration) -> String {
+    let secs = d.as_secs();
+    let (format, minutes) = match secs >= 3600 {
+        true => (&cfg.time_format_long, secs / 60 % 60),
+        false => (&cfg.time_format, secs / 60),
+    };
+    format
+        .replace("{h}", &(secs / 3600).to_string())
+        .replace("{mm}", &format!("{minutes:02}"))
+        .replace("{m}", &minutes.to_string())
+        .replace("{ss}", &format!("{:02}", secs % 60))
+        .replace("{s}", &(secs % 60).to_string())
+}
+
+/// This function queries the position of the active player and stores it in Data.
+/// Nothing is queried if no time fields are rendered, unless the output is waybar, which shows the percentage of the position.
+///
+/// Input:
+/// cfg: Config struct for the program.
+/// data: mutable Data struct for the program.
+/// length: length of the current track, if known.
+pub fn sample_position(cfg: &Config, data: &mut Data, length: Option<Duration>) {
+    data.position = None;
+    if !shows_time(cfg, data) && cfg.output != Output::Waybar {
+        return;
+    }
+    let Some(player) = &data.current_player else {
+        return;
+    };
+
+    match player.get_position() {
+        Ok(position) => {
+            let rate = match player.get_playback_status() {
+                Ok(PlaybackStatus::Playing) => player.get_playback_rate().unwrap_or(1.0),
+                _ => 0.0,
+            };
+            trace!("sample_position: {position:?} of {length:?} at rate {rate}");
+            data.position = Some(PositionSample::new(position, length, rate));
+        }
+        Err(e) => debug!("sample_position: {e}"),
+    }
+}
+
+/// This function re-queries the position of the active player after it seeked, keeping the known track length.
+/// cfg: Config in effect for the active player.
+pub fn resample_position(cfg: &Config, data: &mut Data) {
+    let length = data.position.as_ref().and_then(|p| p.length);
+    sample_position(cfg, data, length);
+}
+
+/// This function updates the output strings of all time fields from the interpolated position.
+/// It does not query the player, so it is cheap enough to call every tick.
+///
+/// Input:
+/// cfg: Config in effect for the active player.
+/// data: mutable Data struct for the program.
+pub fn update_time(cfg: &Config, data: &mut Data) {
+    let keys: Vec<&str> = cfg
+        .field_keys(data.alt_layout)
+        .into_iter()
+        .filter(|key| key.starts_with(TIME_NAMESPACE))
+        .collect();
+    if keys.is_empty() {
+        return;
+    }
+
+    for key in keys {
+        let value = data.position.as_ref().and_then(|sample| {
+            let position = sample.position();
+            match key {
+                "time:position" => Some(format_time(cfg, position)),
+                "time:length" => sample.length.map(|l| format_time(cfg, l)),
+                "time:remaining" => sample.length.map(|l| format_time(cfg, l.saturating_sub(position))),
+                "time:progress" => sample.length.filter(|l| !l.is_zero()).map(|l| {
+                    let fraction = position.as_secs_f64() / l.as_secs_f64();
+                    match &cfg.progress_icons {
+                        Some(bar) => bar.build_string(fraction),
+                        None => ProgressBar::default().build_string(fraction),
+                    }
+                }),
+                _ => {
+                    debug!("update_time: unknown time field {key}");
+                    None
+                }
+            }
+        });
+        match value {
+            Some(value) => data.field_text.insert(key.to_owned(), value),
+            None => data.field_text.remove(key),
+        };
+    }
+}
+
+/// This function returns how long the main loop may sleep before a time field changes.
+/// None if no time field is rendered or the position is not moving.
+pub fn next_time_tick(cfg: &Config, data: &Data) -> Option<Duration> {
+    let cfg = data.config(cfg);
+    match shows_time(cfg, data) {
+        true => data.position.as_ref()?.until_next_second(),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::format_time;
+    use crate::structs::config::{Config, ProgressBar};
+
+    #[test]
+    fn formats_short_and_long_durations() {
+        let cfg = Config::default();
+        assert_eq!(format_time(&cfg, Duration::from_secs(5)), "0:05");
+        assert_eq!(format_time(&cfg, Duration::from_secs(754)), "12:34");
+        assert_eq!(format_time(&cfg, Duration::from_secs(3723)), "1:02:03");
+    }
+
+    #[test]
+    fn progress_bar_uses_half_tokens() {
+        let bar = ProgressBar {
+            width: 4,
+            ..Default::default()
+        };
+        assert_eq!(bar.build_string(0.0), "----");
+        assert_eq!(bar.build_string(0.125), "=---");
+        assert_eq!(bar.build_string(0.5), "##--");
+        assert_eq!(bar.build_string(0.625), "##=-");
+        assert_eq!(bar.build_string(1.5), "####");
+    }
+}